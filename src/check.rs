@@ -0,0 +1,272 @@
+use crate::{
+    Anonymity, HttpError, HttpSupport, Proto, Proxy, Socks4Reply, Socks5Reply, SocksError,
+};
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Step of a proxy check, in the order they happen.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Stage {
+    Connect,
+    Tls,
+    Greeting,
+    Auth,
+    Tunnel,
+    /// Waiting for the target's first byte through the tunnel.
+    FirstByte,
+    /// Reading the target's answer and checking it.
+    Verify,
+}
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Connect => write!(f, "tcp connect"),
+            Stage::Tls => write!(f, "tls"),
+            Stage::Greeting => write!(f, "greeting"),
+            Stage::Auth => write!(f, "auth"),
+            Stage::Tunnel => write!(f, "tunnel"),
+            Stage::FirstByte => write!(f, "first byte"),
+            Stage::Verify => write!(f, "verify"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    Timeout,
+    Refused,
+    /// The connection was reset or closed before the check finished.
+    Reset,
+    /// The proxy does not speak the protocol it was probed with.
+    ProtocolMismatch,
+    BadStatus(u16),
+    /// The target answered without the expected body text.
+    BodyMismatch,
+    AuthRejected,
+    Socks4Reply(Socks4Reply),
+    Socks5Reply(Socks5Reply),
+    Other,
+}
+impl From<&io::Error> for ErrorKind {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+            io::ErrorKind::ConnectionRefused => ErrorKind::Refused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => ErrorKind::Reset,
+            io::ErrorKind::InvalidData => ErrorKind::ProtocolMismatch,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+impl From<&SocksError> for ErrorKind {
+    fn from(e: &SocksError) -> Self {
+        match e {
+            SocksError::Io(e) => e.into(),
+            SocksError::BadVersion(_)
+            | SocksError::UnexpectedMethod(_)
+            | SocksError::UnknownAddressType(_) => ErrorKind::ProtocolMismatch,
+            SocksError::NoAcceptableMethod | SocksError::AuthRejected(_) => ErrorKind::AuthRejected,
+            SocksError::Socks4Reply(reply) => ErrorKind::Socks4Reply(*reply),
+            SocksError::Socks5Reply(reply) => ErrorKind::Socks5Reply(*reply),
+            SocksError::CredentialsTooLong
+            | SocksError::DomainTooLong
+            | SocksError::Ipv6Unsupported => ErrorKind::Other,
+        }
+    }
+}
+impl From<&HttpError> for ErrorKind {
+    fn from(e: &HttpError) -> Self {
+        match e {
+            HttpError::Io(e) => e.into(),
+            HttpError::Malformed => ErrorKind::ProtocolMismatch,
+            HttpError::AuthRequired => ErrorKind::AuthRejected,
+            HttpError::Status(code) => ErrorKind::BadStatus(*code),
+            HttpError::BodyMismatch => ErrorKind::BodyMismatch,
+        }
+    }
+}
+
+/// Why a check failed and at which stage.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CheckError {
+    pub stage: Stage,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.stage, self.message)
+    }
+}
+impl std::error::Error for CheckError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StageTiming {
+    pub stage: Stage,
+    pub elapsed: Duration,
+}
+
+/// Outcome of checking a single proxy. On success `proxy` carries the
+/// detected protocol.
+#[derive(Clone, Debug)]
+pub struct CheckResult {
+    pub proxy: Proxy,
+    /// Protocol that was tested, or the one detected for `Proto::UNKNOWN`
+    /// input.
+    pub proto: Proto,
+    /// Last stage the check reached.
+    pub stage: Stage,
+    pub error: Option<CheckError>,
+    pub timings: Vec<StageTiming>,
+    /// Set for live HTTP(S) proxies when a judge is configured and answered
+    /// their forward GET.
+    pub anonymity: Option<Anonymity>,
+    /// Address the judge saw the request come from.
+    pub exit_ip: Option<IpAddr>,
+    /// Address the proxy was reached at, its host once resolved.
+    pub connect_ip: Option<IpAddr>,
+    /// For live HTTP(S) proxies, which of the tried requests went through.
+    pub http_support: Option<HttpSupport>,
+    /// When the check finished.
+    pub checked_at: SystemTime,
+}
+impl CheckResult {
+    pub(crate) fn new(
+        mut proxy: Proxy,
+        proto: Proto,
+        trace: Trace,
+        error: Option<CheckError>,
+    ) -> Self {
+        let (stage, timings, connect_ip) = trace.finish();
+        if error.is_none() {
+            proxy.proto = proto.clone();
+        }
+        CheckResult {
+            proxy,
+            proto,
+            stage,
+            error,
+            timings,
+            anonymity: None,
+            exit_ip: None,
+            connect_ip,
+            http_support: None,
+            checked_at: SystemTime::now(),
+        }
+    }
+    /// A live result for `proxy` that took `latency_ms` to connect, checked
+    /// at the epoch.
+    #[cfg(test)]
+    pub(crate) fn for_test(proxy: Proxy, latency_ms: u64) -> Self {
+        CheckResult {
+            connect_ip: proxy.host.ip(),
+            proto: proxy.proto.clone(),
+            proxy,
+            stage: Stage::FirstByte,
+            error: None,
+            timings: vec![StageTiming {
+                stage: Stage::Connect,
+                elapsed: Duration::from_millis(latency_ms),
+            }],
+            anonymity: None,
+            exit_ip: None,
+            http_support: None,
+            checked_at: SystemTime::UNIX_EPOCH,
+        }
+    }
+    pub fn is_alive(&self) -> bool {
+        self.error.is_none()
+    }
+    /// Time spent in `stage`, if the check got that far.
+    pub fn stage_latency(&self, stage: Stage) -> Option<Duration> {
+        self.timings
+            .iter()
+            .find(|timing| timing.stage == stage)
+            .map(|timing| timing.elapsed)
+    }
+    /// Total time across all stages.
+    pub fn latency(&self) -> Duration {
+        self.timings.iter().map(|timing| timing.elapsed).sum()
+    }
+    /// Time to open the TCP connection to the proxy.
+    pub fn connect_latency(&self) -> Option<Duration> {
+        self.stage_latency(Stage::Connect)
+    }
+    /// Time from the open connection to an established tunnel, including
+    /// TLS, greeting and authentication.
+    pub fn handshake_latency(&self) -> Option<Duration> {
+        self.stage_latency(Stage::Tunnel)?;
+        Some(
+            self.timings
+                .iter()
+                .filter(|timing| timing.stage > Stage::Connect && timing.stage <= Stage::Tunnel)
+                .map(|timing| timing.elapsed)
+                .sum(),
+        )
+    }
+    /// Whether the proxy egresses from another address than the one we
+    /// connect to, as gateway pools and backconnect services do.
+    pub fn exits_elsewhere(&self) -> bool {
+        match self.exit_ip {
+            Some(exit_ip) => self.connect_ip != Some(exit_ip),
+            None => false,
+        }
+    }
+    /// Time to the first byte of the target's answer through the tunnel.
+    pub fn first_byte_latency(&self) -> Option<Duration> {
+        self.stage_latency(Stage::FirstByte)
+    }
+}
+
+/// Records which stage a probe is in and how long each stage took.
+pub(crate) struct Trace {
+    stage: Stage,
+    started: Instant,
+    timings: Vec<StageTiming>,
+    connect_ip: Option<IpAddr>,
+}
+impl Trace {
+    pub(crate) fn new() -> Self {
+        Trace {
+            stage: Stage::Connect,
+            started: Instant::now(),
+            timings: vec![],
+            connect_ip: None,
+        }
+    }
+    /// Records the address the connection to the proxy went to.
+    pub(crate) fn connected(&mut self, ip: IpAddr) {
+        self.connect_ip = Some(ip);
+    }
+    pub(crate) fn enter(&mut self, stage: Stage) {
+        let now = Instant::now();
+        self.timings.push(StageTiming {
+            stage: self.stage,
+            elapsed: now - self.started,
+        });
+        self.stage = stage;
+        self.started = now;
+    }
+    /// Builds a `CheckError` for a failure in the current stage.
+    pub(crate) fn fail<'a, E>(&self, err: &'a E) -> CheckError
+    where
+        E: fmt::Display,
+        ErrorKind: From<&'a E>,
+    {
+        CheckError {
+            stage: self.stage,
+            kind: err.into(),
+            message: err.to_string(),
+        }
+    }
+    fn finish(mut self) -> (Stage, Vec<StageTiming>, Option<IpAddr>) {
+        let stage = self.stage;
+        self.enter(stage);
+        (stage, self.timings, self.connect_ip)
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{Host, Proxy};
+use async_std::net::{ToSocketAddrs, UdpSocket};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const QTYPE_A: u16 = 1;
+const QTYPE_AAAA: u16 = 28;
+const MAXIMUM_REPLY_SIZE: usize = 1232;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Resolves proxy hostnames, either through the system resolver or by
+/// asking a given DNS server directly. Lookups are cached for as long as the
+/// resolver lives; clones share the cache.
+#[derive(Clone, Default, Debug)]
+pub struct Resolver {
+    /// DNS server queried over UDP, the system resolver when unset.
+    pub server: Option<SocketAddr>,
+    cache: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+}
+impl Resolver {
+    pub fn new(server: Option<SocketAddr>) -> Self {
+        Resolver {
+            server,
+            ..Default::default()
+        }
+    }
+    /// All A and AAAA records of `name`. A failed AAAA query counts as no
+    /// records, as many resolvers and middleboxes drop or refuse them.
+    pub async fn resolve(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        if let Some(ips) = self.cache.lock().unwrap().get(&name) {
+            return Ok(ips.clone());
+        }
+        let ips = match self.server {
+            Some(server) => {
+                let (a, aaaa) = futures::join!(
+                    query(server, &name, QTYPE_A),
+                    query(server, &name, QTYPE_AAAA)
+                );
+                match (a, aaaa) {
+                    (Ok(mut ips), aaaa) => {
+                        ips.extend(aaaa.unwrap_or_default());
+                        ips
+                    }
+                    (Err(_), Ok(ips)) => ips,
+                    (Err(e), Err(_)) => return Err(e),
+                }
+            }
+            None => (name.as_str(), 0)
+                .to_socket_addrs()
+                .await?
+                .map(|addr| addr.ip())
+                .collect(),
+        };
+        if ips.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address found for {}", name),
+            ));
+        }
+        self.cache.lock().unwrap().insert(name, ips.clone());
+        Ok(ips)
+    }
+    /// The address to connect to for `host`, the first record for names.
+    pub async fn lookup(&self, host: &Host) -> io::Result<IpAddr> {
+        match host {
+            Host::Ip(ip) => Ok(*ip),
+            Host::Domain(name) => Ok(self.resolve(name).await?[0]),
+        }
+    }
+}
+
+/// Replaces every hostname proxy with one proxy per A/AAAA record of its
+/// name. Names that do not resolve are kept as they are, so their checks
+/// report the failure.
+pub async fn expand_hosts(proxies: Vec<Proxy>, resolver: &Resolver) -> Vec<Proxy> {
+    let mut expanded = vec![];
+    for proxy in proxies {
+        let ips = match &proxy.host {
+            Host::Domain(name) => resolver.resolve(name).await.unwrap_or_default(),
+            Host::Ip(_) => vec![],
+        };
+        if ips.is_empty() {
+            expanded.push(proxy);
+            continue;
+        }
+        for ip in ips {
+            let mut proxy = proxy.clone();
+            proxy.host = Host::Ip(ip);
+            expanded.push(proxy);
+        }
+    }
+    expanded
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Sends a single recursive query for `name` to `server` and returns the
+/// addresses of the answer records of type `qtype`.
+async fn query(server: SocketAddr, name: &str, qtype: u16) -> io::Result<Vec<IpAddr>> {
+    let id = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.subsec_nanos() as u16)
+        .unwrap_or_default();
+    let mut request = vec![];
+    request.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question
+    request.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid("invalid host name"));
+        }
+        request.push(label.len() as u8);
+        request.extend_from_slice(label.as_bytes());
+    }
+    request.push(0);
+    request.extend_from_slice(&qtype.to_be_bytes());
+    request.extend_from_slice(&[0, 1]);
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.send_to(&request, server).await?;
+    let mut reply = vec![0; MAXIMUM_REPLY_SIZE];
+    let len = loop {
+        let (len, from) =
+            async_std::io::timeout(QUERY_TIMEOUT, socket.recv_from(&mut reply)).await?;
+        if from == server && len >= 2 && reply[..2] == id.to_be_bytes() {
+            break len;
+        }
+    };
+    parse_reply(&reply[..len], qtype)
+}
+
+/// Skips a possibly compressed name starting at `pos`.
+fn skip_name(reply: &[u8], mut pos: usize) -> io::Result<usize> {
+    loop {
+        match reply.get(pos) {
+            Some(0) => return Ok(pos + 1),
+            Some(len) if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            Some(len) => pos += 1 + *len as usize,
+            None => return Err(invalid("truncated dns reply")),
+        }
+    }
+}
+
+fn parse_reply(reply: &[u8], qtype: u16) -> io::Result<Vec<IpAddr>> {
+    if reply.len() < 12 {
+        return Err(invalid("truncated dns reply"));
+    }
+    match reply[3] & 0x0f {
+        0 => {}
+        3 => return Ok(vec![]),
+        rcode => return Err(invalid(&format!("dns server answered rcode {}", rcode))),
+    }
+    let questions = u16::from_be_bytes([reply[4], reply[5]]);
+    let answers = u16::from_be_bytes([reply[6], reply[7]]);
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(reply, pos)? + 4;
+    }
+    let mut ips = vec![];
+    for _ in 0..answers {
+        pos = skip_name(reply, pos)?;
+        let record = reply
+            .get(pos..pos + 10)
+            .ok_or_else(|| invalid("truncated dns reply"))?;
+        let rtype = u16::from_be_bytes([record[0], record[1]]);
+        let rdlength = u16::from_be_bytes([record[8], record[9]]) as usize;
+        pos += 10;
+        let rdata = reply
+            .get(pos..pos + rdlength)
+            .ok_or_else(|| invalid("truncated dns reply"))?;
+        pos += rdlength;
+        match (rtype, rdata.len()) {
+            (QTYPE_A, 4) if qtype == QTYPE_A => {
+                ips.push(IpAddr::from(<[u8; 4]>::try_from(rdata).unwrap()))
+            }
+            (QTYPE_AAAA, 16) if qtype == QTYPE_AAAA => {
+                ips.push(IpAddr::from(<[u8; 16]>::try_from(rdata).unwrap()))
+            }
+            _ => {}
+        }
+    }
+    Ok(ips)
+}
+
+/// Starts a DNS server on localhost that answers every A query with
+/// 127.0.0.1 and every AAAA query with ::1, or drops AAAA queries unless
+/// `aaaa`, counting the queries it gets.
+#[cfg(test)]
+pub(crate) async fn fake_dns_server(aaaa: bool) -> (SocketAddr, Arc<Mutex<usize>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let queries = Arc::new(Mutex::new(0));
+    let counter = queries.clone();
+    async_std::task::spawn(async move {
+        let mut buf = [0; 512];
+        while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+            *counter.lock().unwrap() += 1;
+            let question_end = skip_name(&buf[..len], 12).unwrap() + 4;
+            let qtype = u16::from_be_bytes([buf[question_end - 4], buf[question_end - 3]]);
+            if qtype == QTYPE_AAAA && !aaaa {
+                continue;
+            }
+            let mut reply = buf[..question_end].to_vec();
+            reply[2] = 0x81;
+            reply[3] = 0x80;
+            reply[7] = 1;
+            reply.extend_from_slice(&[0xc0, 12]);
+            reply.extend_from_slice(&qtype.to_be_bytes());
+            reply.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+            let rdata = match qtype {
+                QTYPE_A => Ipv4Addr::LOCALHOST.octets().to_vec(),
+                _ => Ipv6Addr::LOCALHOST.octets().to_vec(),
+            };
+            reply.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            reply.extend_from_slice(&rdata);
+            socket.send_to(&reply, from).await.unwrap();
+        }
+    });
+    (addr, queries)
+}
+
+#[tokio::test]
+async fn test_resolver_queries_server_and_caches() {
+    let (server, queries) = fake_dns_server(true).await;
+    let resolver = Resolver::new(Some(server));
+    let expected: Vec<IpAddr> = vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()];
+    assert_eq!(resolver.resolve("Proxy.Example.").await.unwrap(), expected);
+    assert_eq!(resolver.resolve("proxy.example").await.unwrap(), expected);
+    assert_eq!(*queries.lock().unwrap(), 2);
+    let proxies = vec![Proxy::new(crate::Proto::SOCKS5, "proxy.example", 1080)];
+    let expanded = expand_hosts(proxies, &resolver).await;
+    let hosts = expanded
+        .iter()
+        .map(|proxy| proxy.host.ip())
+        .collect::<Vec<_>>();
+    assert_eq!(hosts, expected.into_iter().map(Some).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_resolver_survives_dropped_aaaa_queries() {
+    let (server, queries) = fake_dns_server(false).await;
+    let resolver = Resolver::new(Some(server));
+    let ips = resolver.resolve("proxy.example").await.unwrap();
+    assert_eq!(ips, vec![IpAddr::from(Ipv4Addr::LOCALHOST)]);
+    assert_eq!(*queries.lock().unwrap(), 2);
+}
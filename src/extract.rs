@@ -0,0 +1,271 @@
+use crate::{parse_padded_ipv4, Host, HostFilter, LineError, ParsedList, Proto, Proxy};
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Tags ending a row of an HTML page; every other tag only separates text.
+const ROW_TAGS: [&str; 7] = ["tr", "br", "p", "div", "li", "table", "pre"];
+
+/// An `ip:port` pair found in a row, with its byte range.
+struct Found {
+    host: Host,
+    port: u16,
+    start: usize,
+    end: usize,
+}
+
+/// Pulls candidate proxies out of free-form text: pasted web pages, forum
+/// posts or JSON blobs. An address counts when a port follows it, after a
+/// `:`, spaces, quotes, a `port` key or, in HTML, a table cell boundary.
+/// Protocol names next to it (`socks5://`, a `SOCKS5` cell, a `"protocol":
+/// "http"` field) become its protocol hints. Addresses `filter` rejects are
+/// reported with the line they were found on.
+pub fn extract_proxies(text: &str, filter: &HostFilter) -> ParsedList {
+    let mut list = ParsedList::default();
+    for (line, row) in rows(text) {
+        let found = find_proxies(&row);
+        for (i, pair) in found.iter().enumerate() {
+            let context = match found.len() {
+                1 => row.as_str(),
+                _ => &row[pair.end..found.get(i + 1).map_or(row.len(), |next| next.start)],
+            };
+            let mut protos = scheme_before(&row[..pair.start]);
+            for proto in hints(context) {
+                if !protos.contains(&proto) {
+                    protos.push(proto);
+                }
+            }
+            let mut proxy = Proxy {
+                proto: Proto::UNKNOWN,
+                host: pair.host.clone(),
+                port: pair.port,
+                ..Default::default()
+            };
+            proxy.set_protos(protos);
+            match proxy.host.ip().and_then(|ip| filter.rejects(ip)) {
+                None => list.proxies.push(proxy),
+                Some(range) => list.rejected.push(LineError {
+                    source: String::new(),
+                    line,
+                    text: proxy.to_string(),
+                    reason: format!("{} address", range),
+                }),
+            }
+        }
+    }
+    list.dedupe();
+    list
+}
+
+/// Splits `text` into rows, each with the line it starts on. Rows end at
+/// newlines and braces, so every JSON object is a row of its own. In HTML
+/// they end at table rows and line breaks instead, and the other tags turn
+/// into spaces so neighbouring cells stay apart.
+fn rows(text: &str) -> Vec<(usize, String)> {
+    let html = text.contains("</");
+    let mut rows = vec![];
+    let mut row = String::new();
+    let (mut line, mut start) = (1, 1);
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let ends_row = match c {
+            '\n' => {
+                line += 1;
+                !html
+            }
+            '{' | '}' => true,
+            '<' if html => {
+                let tag = chars.by_ref().take_while(|c| *c != '>').collect::<String>();
+                line += tag.matches('\n').count();
+                let name = tag
+                    .trim_start_matches('/')
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                ROW_TAGS.contains(&name.as_str())
+            }
+            _ => false,
+        };
+        if ends_row {
+            rows.push((start, std::mem::take(&mut row)));
+            start = line;
+        } else if c.is_whitespace() || c == '<' {
+            row.push(' ');
+        } else {
+            row.push(c);
+        }
+    }
+    rows.push((start, row));
+    rows.into_iter()
+        .filter(|(_, row)| !row.trim().is_empty())
+        .map(|(line, row)| (line, row.replace("&nbsp;", " ").replace("&#58;", ":")))
+        .collect()
+}
+
+/// Every address followed by a port in `row`, in order.
+fn find_proxies(row: &str) -> Vec<Found> {
+    let bytes = row.as_bytes();
+    let mut found = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let boundary = i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'.');
+        let host = match bytes[i] {
+            b'0'..=b'9' if boundary => ipv4_at(row, i),
+            b'[' => ipv6_at(row, i),
+            _ => None,
+        };
+        match host.and_then(|(host, end)| Some((host, port_after(row, end)?))) {
+            Some((host, (port, end))) => {
+                found.push(Found {
+                    host,
+                    port,
+                    start: i,
+                    end,
+                });
+                i = end;
+            }
+            None => i += 1,
+        }
+    }
+    found
+}
+
+/// The dotted quad starting at `start`, and where it ends.
+fn ipv4_at(row: &str, start: usize) -> Option<(Host, usize)> {
+    let len = row[start..]
+        .bytes()
+        .take_while(|b| b.is_ascii_digit() || *b == b'.')
+        .count();
+    // an address ending a sentence
+    let quad = row[start..start + len].trim_end_matches('.');
+    let end = start + quad.len();
+    if row[end..].starts_with(|c: char| c.is_alphanumeric()) {
+        return None;
+    }
+    let ip = parse_padded_ipv4(quad)?;
+    Some((Host::Ip(ip), end))
+}
+
+/// The bracketed IPv6 address starting at `start`, and where it ends.
+fn ipv6_at(row: &str, start: usize) -> Option<(Host, usize)> {
+    let len = row[start..].find(']')?;
+    let ip = row[start + 1..start + len].parse::<Ipv6Addr>().ok()?;
+    Some((Host::Ip(IpAddr::V6(ip).to_canonical()), start + len + 1))
+}
+
+/// The port following an address that ends at `start`, and where it ends.
+fn port_after(row: &str, start: usize) -> Option<(u16, usize)> {
+    let bytes = row.as_bytes();
+    let mut i = start;
+    loop {
+        while bytes.get(i).is_some_and(|b| b" \t\"',:;=".contains(b)) {
+            i += 1;
+        }
+        match row.get(i..i + 4) {
+            Some(key) if key.eq_ignore_ascii_case("port") => i += 4,
+            _ => break,
+        }
+    }
+    let digits = bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let end = i + digits;
+    if !(1..=5).contains(&digits)
+        || bytes
+            .get(end)
+            .is_some_and(|b| *b == b'.' || b.is_ascii_alphanumeric())
+    {
+        return None;
+    }
+    let port = row[i..end].parse::<u16>().ok().filter(|port| *port != 0)?;
+    Some((port, end))
+}
+
+/// The protocol of a `scheme://` written right before an address.
+fn scheme_before(text: &str) -> Vec<Proto> {
+    match text.strip_suffix("://") {
+        Some(text) => hints(
+            text.rsplit(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap(),
+        ),
+        None => vec![],
+    }
+}
+
+/// Protocols named in `text`, in the order they appear.
+fn hints(text: &str) -> Vec<Proto> {
+    let mut protos = vec![];
+    for word in text.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let named: &[Proto] = match word.to_ascii_lowercase().as_str() {
+            "http" => &[Proto::HTTP],
+            "https" => &[Proto::HTTPS],
+            "socks4" | "socks4a" => &[Proto::SOCKS4],
+            "socks5" | "socks5h" => &[Proto::SOCKS5],
+            "socks" => &[Proto::SOCKS5, Proto::SOCKS4],
+            _ => &[],
+        };
+        for proto in named {
+            if !protos.contains(proto) {
+                protos.push(proto.clone());
+            }
+        }
+    }
+    protos
+}
+
+#[test]
+fn test_extract_proxies_from_text_html_and_json() {
+    let text = r#"
+Fresh list! socks5://8.8.4.4:1080 and 1.1.1.1:3128 (http), last one 9.9.9.9.
+<table>
+  <tr><th>IP</th><th>Port</th><th>Type</th></tr>
+  <tr><td>5.6.7.8</td>
+      <td>8080</td><td>HTTPS</td></tr>
+  <tr><td>10.0.0.1</td><td>80</td><td>HTTP</td></tr>
+</table>
+[{"ip": "4.4.4.4", "port": 1081, "protocols": ["socks4", "socks5"]},
+ {"ip": "4.4.4.4", "port": "1081"}, {"ip": "[2606:4700::1]:443"}]
+version 1.2.3 build 45
+"#;
+    let list = extract_proxies(text, &HostFilter::all());
+    let proxies = list
+        .proxies
+        .iter()
+        .map(|proxy| (proxy.to_string(), proxy.candidates()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        proxies,
+        vec![
+            ("socks5://8.8.4.4:1080".to_string(), vec![Proto::SOCKS5]),
+            ("http://1.1.1.1:3128".to_string(), vec![Proto::HTTP]),
+            ("https://5.6.7.8:8080".to_string(), vec![Proto::HTTPS]),
+            (
+                "4.4.4.4:1081".to_string(),
+                vec![Proto::SOCKS4, Proto::SOCKS5]
+            ),
+            (
+                "[2606:4700::1]:443".to_string(),
+                vec![Proto::SOCKS5, Proto::SOCKS4, Proto::HTTP, Proto::HTTPS]
+            ),
+        ]
+    );
+    assert_eq!(list.duplicates, 1);
+    assert_eq!(list.rejected.len(), 1);
+    assert_eq!(list.rejected[0].line, 7);
+    assert_eq!(list.rejected[0].text, "http://10.0.0.1:80");
+}
+
+#[test]
+fn test_extracted_hints_survive_list_lines() {
+    let text = r#"{"ip": "4.4.4.4", "port": 1081, "protocols": ["socks4", "socks5"]}"#;
+    let list = extract_proxies(text, &HostFilter::all());
+    let lines = list.proxies[0].list_lines();
+    assert_eq!(
+        lines,
+        vec!["socks4://4.4.4.4:1081", "socks5://4.4.4.4:1081"]
+    );
+    let reread = crate::parse_list(lines, &HostFilter::all());
+    assert_eq!(reread.proxies.len(), 1);
+    assert_eq!(
+        reread.proxies[0].candidates(),
+        vec![Proto::SOCKS4, Proto::SOCKS5]
+    );
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Kind of address no public proxy can sit on.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Range {
+    /// RFC 1918 and IPv6 unique local addresses.
+    Private,
+    Loopback,
+    Multicast,
+    /// `0.0.0.0/8`, `240.0.0.0/4` and the unspecified IPv6 address.
+    Reserved,
+    /// Link-local, shared, documentation, benchmarking and other special
+    /// purpose blocks.
+    Bogon,
+}
+impl Range {
+    /// The range `ip` belongs to, if it is not a public unicast address.
+    pub fn of(ip: IpAddr) -> Option<Range> {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => Self::of_v4(ip),
+            IpAddr::V6(ip) => Self::of_v6(ip),
+        }
+    }
+    fn of_v4(ip: Ipv4Addr) -> Option<Range> {
+        let [a, b, c, _] = ip.octets();
+        if ip.is_private() {
+            Some(Range::Private)
+        } else if ip.is_loopback() {
+            Some(Range::Loopback)
+        } else if ip.is_multicast() {
+            Some(Range::Multicast)
+        } else if a == 0 || a >= 240 {
+            Some(Range::Reserved)
+        } else if ip.is_link_local()
+            || ip.is_documentation()
+            // shared address space, RFC 6598
+            || (a == 100 && (64..128).contains(&b))
+            // benchmarking, RFC 2544
+            || (a == 198 && (b == 18 || b == 19))
+            // IETF protocol assignments and the 6to4 relay anycast
+            || (a == 192 && b == 0 && c == 0)
+            || (a == 192 && b == 88 && c == 99)
+        {
+            Some(Range::Bogon)
+        } else {
+            None
+        }
+    }
+    fn of_v6(ip: Ipv6Addr) -> Option<Range> {
+        let first = ip.segments()[0];
+        if first & 0xfe00 == 0xfc00 {
+            Some(Range::Private)
+        } else if ip.is_loopback() {
+            Some(Range::Loopback)
+        } else if ip.is_multicast() {
+            Some(Range::Multicast)
+        } else if ip.is_unspecified() {
+            Some(Range::Reserved)
+        } else if first & 0xffc0 == 0xfe80
+            // documentation
+            || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+            // only 2000::/3 is handed out as global unicast
+            || first & 0xe000 != 0x2000
+        {
+            Some(Range::Bogon)
+        } else {
+            None
+        }
+    }
+}
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// Address ranges whose proxies are dropped while loading a list. `bogon`
+/// covers every range, the others only their own.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct HostFilter {
+    pub private: bool,
+    pub loopback: bool,
+    pub multicast: bool,
+    pub reserved: bool,
+    pub bogon: bool,
+}
+impl HostFilter {
+    /// Rejects everything that is not a public unicast address.
+    pub fn all() -> Self {
+        HostFilter {
+            bogon: true,
+            ..Default::default()
+        }
+    }
+    /// The range `ip` was rejected for, if this filter rejects it.
+    pub fn rejects(&self, ip: IpAddr) -> Option<Range> {
+        let range = Range::of(ip)?;
+        let rejected = self.bogon
+            || match range {
+                Range::Private => self.private,
+                Range::Loopback => self.loopback,
+                Range::Multicast => self.multicast,
+                Range::Reserved => self.reserved,
+                Range::Bogon => false,
+            };
+        rejected.then_some(range)
+    }
+}
+impl FromStr for HostFilter {
+    type Err = String;
+    /// Comma separated ranges, e.g. `private,loopback`, or `all`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = HostFilter::default();
+        for range in s.split(',').map(|range| range.trim().to_lowercase()) {
+            match range.as_str() {
+                "private" => filter.private = true,
+                "loopback" => filter.loopback = true,
+                "multicast" => filter.multicast = true,
+                "reserved" => filter.reserved = true,
+                "bogon" | "all" => filter.bogon = true,
+                _ => return Err(format!("unknown address range \"{}\"", range)),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+#[test]
+fn test_host_filter_ranges() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let filter: HostFilter = "private,loopback".parse().unwrap();
+    assert_eq!(filter.rejects(ip("10.0.0.1")), Some(Range::Private));
+    assert_eq!(filter.rejects(ip("127.0.0.1")), Some(Range::Loopback));
+    assert_eq!(
+        filter.rejects(ip("::ffff:192.168.1.1")),
+        Some(Range::Private)
+    );
+    assert_eq!(filter.rejects(ip("224.0.0.1")), None);
+    assert_eq!(filter.rejects(ip("8.8.8.8")), None);
+    let all = HostFilter::all();
+    for bogon in [
+        "100.64.0.1",
+        "169.254.1.1",
+        "198.51.100.7",
+        "0.1.2.3",
+        "fe80::1",
+    ] {
+        assert!(all.rejects(ip(bogon)).is_some(), "{} passed", bogon);
+    }
+    assert_eq!(all.rejects(ip("8.8.8.8")), None);
+    assert_eq!(all.rejects(ip("2606:4700::1111")), None);
+    assert!("private,nowhere".parse::<HostFilter>().is_err());
+}
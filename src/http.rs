@@ -0,0 +1,238 @@
+use crate::Credentials;
+use async_std::io::{ReadExt, WriteExt};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::{AsyncRead, AsyncWrite};
+use httparse::{Response, EMPTY_HEADER};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+const MAXIMUM_RESPONSE_HEADERS: usize = 16;
+const MAXIMUM_RESPONSE_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum HttpError {
+    Io(io::Error),
+    /// The proxy answered with something that is not an HTTP response.
+    Malformed,
+    /// 407: the proxy wants credentials, or refused the ones sent.
+    AuthRequired,
+    Status(u16),
+    /// The response body lacks the expected text.
+    BodyMismatch,
+}
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Io(e) => write!(f, "io error: {}", e),
+            HttpError::Malformed => write!(f, "malformed http response"),
+            HttpError::AuthRequired => write!(f, "proxy authentication required or failed"),
+            HttpError::Status(code) => write!(f, "unexpected status code {}", code),
+            HttpError::BodyMismatch => write!(f, "response body lacks the expected text"),
+        }
+    }
+}
+impl std::error::Error for HttpError {}
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+/// Which requests an HTTP(S) proxy is tried with.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum HttpMode {
+    /// A CONNECT tunnel.
+    #[default]
+    Connect,
+    /// An absolute-form `GET http://host/path` the proxy forwards itself.
+    Forward,
+    /// Both of the above; the proxy passes if either works.
+    Both,
+}
+impl HttpMode {
+    pub fn tries_connect(&self) -> bool {
+        *self != HttpMode::Forward
+    }
+    pub fn tries_forward(&self) -> bool {
+        *self != HttpMode::Connect
+    }
+}
+impl fmt::Display for HttpMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+impl FromStr for HttpMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "connect" => Ok(HttpMode::Connect),
+            "forward" => Ok(HttpMode::Forward),
+            "both" => Ok(HttpMode::Both),
+            _ => Err(format!("unknown http mode \"{}\"", s)),
+        }
+    }
+}
+
+/// Requests a live HTTP(S) proxy was found to handle, out of those tried.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HttpSupport {
+    Connect,
+    Forward,
+    Both,
+}
+impl HttpSupport {
+    pub(crate) fn from_flags(connect: bool, forward: bool) -> Option<Self> {
+        match (connect, forward) {
+            (true, true) => Some(HttpSupport::Both),
+            (true, false) => Some(HttpSupport::Connect),
+            (false, true) => Some(HttpSupport::Forward),
+            (false, false) => None,
+        }
+    }
+}
+impl fmt::Display for HttpSupport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+fn make_request(host: &str, port: u16) -> String {
+    format!(
+        "CONNECT {0}:{1} HTTP/1.1\r\n\
+         Host: {0}:{1}\r\n\
+         Proxy-Connection: Keep-Alive\r\n",
+        host, port
+    )
+}
+fn make_request_without_basic_auth(host: &str, port: u16) -> String {
+    let mut request = make_request(host, port);
+    request.push_str("\r\n");
+    request
+}
+fn make_request_with_basic_auth(host: &str, port: u16, credentials: &Credentials) -> String {
+    let mut request = make_request(host, port);
+    request.push_str(&format!(
+        "Proxy-Authorization: {}\r\n\r\n",
+        basic_auth(credentials)
+    ));
+    request
+}
+/// Value of a `Proxy-Authorization` header for the given credentials.
+pub fn basic_auth(credentials: &Credentials) -> String {
+    let token = format!("{}:{}", credentials.username, credentials.password);
+    format!("Basic {}", STANDARD.encode(token))
+}
+
+/// Reads from `stream` until a full response head has arrived and returns
+/// its status code.
+async fn read_status<S>(stream: &mut S) -> Result<u16, HttpError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = vec![0; MAXIMUM_RESPONSE_SIZE];
+    let mut len = 0;
+    loop {
+        let read = stream.read(&mut buf[len..]).await?;
+        if read == 0 {
+            return Err(HttpError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        len += read;
+        let mut response_headers = [EMPTY_HEADER; MAXIMUM_RESPONSE_HEADERS];
+        let mut response = Response::new(&mut response_headers[..]);
+        match response.parse(&buf[..len]) {
+            Ok(status) if status.is_complete() => {
+                return response.code.ok_or(HttpError::Malformed);
+            }
+            Ok(_) if len < buf.len() => continue,
+            _ => return Err(HttpError::Malformed),
+        }
+    }
+}
+
+/// Writes `request` and returns the status code of the answer's head.
+pub(crate) async fn request_status<S>(stream: &mut S, request: &str) -> Result<u16, HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(request.as_bytes()).await?;
+    read_status(stream).await
+}
+
+/// Sends a HEAD request for `host` through an established tunnel and waits
+/// for the first byte of the answer.
+pub(crate) async fn first_byte<S>(stream: &mut S, host: &str) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "HEAD / HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Connection: close\r\n\r\n",
+        host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut buf = [0; 1];
+    match stream.read(&mut buf).await? {
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Asks an HTTP proxy to open a tunnel to `host:port` with a CONNECT
+/// request, sending a Basic `Proxy-Authorization` header when credentials
+/// are given.
+pub async fn http_connect<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&Credentials>,
+) -> Result<(), HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = match credentials {
+        Some(credentials) => make_request_with_basic_auth(host, port, credentials),
+        None => make_request_without_basic_auth(host, port),
+    };
+    stream.write_all(request.as_bytes()).await?;
+    match read_status(stream).await? {
+        200..=299 => Ok(()),
+        407 => Err(HttpError::AuthRequired),
+        code => Err(HttpError::Status(code)),
+    }
+}
+
+#[tokio::test]
+async fn test_http_connect_basic_auth() {
+    use async_std::net::{TcpListener, TcpStream};
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = async_std::task::spawn(async move {
+        for reply in ["HTTP/1.1 200 OK\r\n\r\n", "HTTP/1.1 407 Proxy Auth\r\n\r\n"] {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let len = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..len]).to_string();
+            assert!(request.starts_with("CONNECT example.com:443 HTTP/1.1\r\n"));
+            assert!(request.contains("Proxy-Authorization: Basic Ym9iOmh1bnRlcg==\r\n"));
+            assert!(request.ends_with("\r\n\r\n"));
+            socket.write_all(reply.as_bytes()).await.unwrap();
+        }
+    });
+    let credentials = Credentials::new("bob", "hunter");
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert!(
+        http_connect(&mut stream, "example.com", 443, Some(&credentials))
+            .await
+            .is_ok()
+    );
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    match http_connect(&mut stream, "example.com", 443, Some(&credentials)).await {
+        Err(HttpError::AuthRequired) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    server.await;
+}
@@ -0,0 +1,355 @@
+use async_std::io::{ReadExt, WriteExt};
+use async_std::net::TcpListener;
+use async_tls::TlsAcceptor;
+use futures::{AsyncRead, AsyncWrite};
+use httparse::{Request, EMPTY_HEADER};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{NoClientAuth, ServerConfig};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+const MAXIMUM_REQUEST_HEADERS: usize = 64;
+const MAXIMUM_REQUEST_SIZE: usize = 16 * 1024;
+
+/// Headers a proxy adds when it admits to being one, lowercase.
+const PROXY_HEADERS: [&str; 11] = [
+    "via",
+    "forwarded",
+    "forwarded-for",
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-real-ip",
+    "x-client-ip",
+    "client-ip",
+    "x-originating-ip",
+    "x-proxy-id",
+    "proxy-client-ip",
+];
+
+/// How much a proxy reveals to the sites it connects to.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Anonymity {
+    /// Passes the client's real IP along.
+    Transparent,
+    /// Hides the client IP but announces itself as a proxy.
+    Anonymous,
+    /// Looks like a direct client.
+    Elite,
+}
+impl fmt::Display for Anonymity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+impl FromStr for Anonymity {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "transparent" => Ok(Anonymity::Transparent),
+            "anonymous" => Ok(Anonymity::Anonymous),
+            "elite" => Ok(Anonymity::Elite),
+            _ => Err(format!("unknown anonymity level \"{}\"", s)),
+        }
+    }
+}
+
+/// What a judge saw of a request: the peer address and the headers it
+/// received, names lowercased.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct JudgeEcho {
+    pub ip: Option<IpAddr>,
+    pub headers: Vec<(String, String)>,
+    pub request_line: Option<String>,
+}
+impl JudgeEcho {
+    /// Parses a judge answer: the JSON object served by `open_proxies judge`
+    /// (`{"ip": .., "request_line": .., "headers": {..}}`), or the
+    /// `NAME = value` / `Name: value` listings most public judges print.
+    pub fn parse(body: &str) -> Self {
+        match serde_json::from_str::<serde_json::Value>(body) {
+            Ok(serde_json::Value::Object(object)) => {
+                let text = |key: &str| object.get(key).and_then(|v| v.as_str());
+                let headers = match object.get("headers") {
+                    Some(serde_json::Value::Object(headers)) => headers
+                        .iter()
+                        .map(|(name, value)| {
+                            let value = match value.as_str() {
+                                Some(value) => value.to_string(),
+                                None => value.to_string(),
+                            };
+                            (name.to_lowercase(), value)
+                        })
+                        .collect(),
+                    _ => vec![],
+                };
+                JudgeEcho {
+                    ip: text("ip").and_then(|ip| ip.parse().ok()),
+                    headers,
+                    request_line: text("request_line").map(|line| line.to_string()),
+                }
+            }
+            _ => Self::parse_listing(body),
+        }
+    }
+    fn parse_listing(body: &str) -> Self {
+        let mut echo = JudgeEcho::default();
+        for line in body.lines() {
+            let split = match (line.find('='), line.find(':')) {
+                (Some(eq), Some(colon)) => eq.min(colon),
+                (Some(i), None) | (None, Some(i)) => i,
+                (None, None) => continue,
+            };
+            let name = line[..split].trim().to_uppercase();
+            let value = line[split + 1..].trim().to_string();
+            if name.is_empty() || name.contains(' ') {
+                continue;
+            }
+            if name == "REMOTE_ADDR" {
+                echo.ip = value.parse().ok();
+                continue;
+            }
+            let name = name
+                .strip_prefix("HTTP_")
+                .unwrap_or(&name)
+                .replace('_', "-")
+                .to_lowercase();
+            echo.headers.push((name, value));
+        }
+        echo
+    }
+    /// The JSON object `parse` reads back, as served by the built-in judge.
+    pub fn to_json(&self) -> String {
+        let headers: serde_json::Map<String, serde_json::Value> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone().into()))
+            .collect();
+        serde_json::json!({
+            "ip": self.ip.map(|ip| ip.to_string()),
+            "request_line": self.request_line,
+            "headers": headers,
+        })
+        .to_string()
+    }
+    /// Value of the first header called `name`, case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+    /// Classifies the proxy this echo came through. Without `real_ip` a
+    /// leaked client address cannot be recognised, so the result is at best
+    /// a guess between anonymous and elite.
+    pub fn anonymity(&self, real_ip: Option<IpAddr>) -> Anonymity {
+        if let Some(real_ip) = real_ip {
+            let real_ip = real_ip.to_canonical();
+            if self.ip == Some(real_ip)
+                || self
+                    .headers
+                    .iter()
+                    .any(|(_, v)| listed_ips(v).contains(&real_ip))
+            {
+                return Anonymity::Transparent;
+            }
+        }
+        if self
+            .headers
+            .iter()
+            .any(|(name, _)| PROXY_HEADERS.contains(&name.as_str()))
+        {
+            Anonymity::Anonymous
+        } else {
+            Anonymity::Elite
+        }
+    }
+}
+
+/// Addresses listed in a header value such as `X-Forwarded-For: a, b:port`
+/// or `Forwarded: for="[2001:db8::1]:4711";proto=http`.
+fn listed_ips(value: &str) -> Vec<IpAddr> {
+    value
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter_map(|token| {
+            let token = token.trim_matches('"');
+            let token = match token.get(..4) {
+                Some(key) if key.eq_ignore_ascii_case("for=") => &token[4..],
+                _ => token,
+            };
+            let token = token.trim_matches('"');
+            let ip = match token.parse::<SocketAddr>() {
+                Ok(addr) => addr.ip(),
+                Err(_) => token
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse::<IpAddr>()
+                    .ok()?,
+            };
+            Some(ip.to_canonical())
+        })
+        .collect()
+}
+
+/// Loads a PEM certificate chain and its PKCS#8 or RSA private key for the
+/// https side of the judge.
+pub fn judge_tls_acceptor(cert_path: &str, key_path: &str) -> io::Result<TlsAcceptor> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let chain = certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| invalid("unreadable certificate file"))?;
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+        .map_err(|_| invalid("unreadable key file"))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .map_err(|_| invalid("unreadable key file"))?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("no private key found"))?;
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(chain, key)
+        .map_err(|e| invalid(&e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Serves the judge on `listener` until it fails: every request is answered
+/// with a `JudgeEcho` of itself as JSON. Connections are wrapped in TLS when
+/// an acceptor is given.
+pub async fn run_judge(listener: TcpListener, tls: Option<TlsAcceptor>) -> io::Result<()> {
+    loop {
+        let (mut socket, peer) = listener.accept().await?;
+        let tls = tls.clone();
+        async_std::task::spawn(async move {
+            let ip = peer.ip().to_canonical();
+            let _ = match tls {
+                Some(tls) => match tls.accept(socket).await {
+                    Ok(mut stream) => answer(&mut stream, ip).await,
+                    Err(e) => Err(e),
+                },
+                None => answer(&mut socket, ip).await,
+            };
+        });
+    }
+}
+
+/// Reads one request head from `stream` and echoes it back.
+async fn answer<S>(stream: &mut S, ip: IpAddr) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; MAXIMUM_REQUEST_SIZE];
+    let mut len = 0;
+    let echo = loop {
+        let read = stream.read(&mut buf[len..]).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        len += read;
+        let mut request_headers = [EMPTY_HEADER; MAXIMUM_REQUEST_HEADERS];
+        let mut request = Request::new(&mut request_headers[..]);
+        match request.parse(&buf[..len]) {
+            Ok(httparse::Status::Complete(_)) => {
+                break Some(JudgeEcho {
+                    ip: Some(ip),
+                    headers: request
+                        .headers
+                        .iter()
+                        .map(|h| {
+                            (
+                                h.name.to_string(),
+                                String::from_utf8_lossy(h.value).into_owned(),
+                            )
+                        })
+                        .collect(),
+                    request_line: Some(format!(
+                        "{} {} HTTP/1.{}",
+                        request.method.unwrap_or_default(),
+                        request.path.unwrap_or_default(),
+                        request.version.unwrap_or(1)
+                    )),
+                })
+            }
+            Ok(_) if len < buf.len() => continue,
+            _ => break None,
+        }
+    };
+    let response = match echo {
+        Some(echo) => {
+            let body = echo.to_json();
+            format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        None => "HTTP/1.1 400 Bad Request\r\n\
+                 Content-Length: 0\r\n\
+                 Connection: close\r\n\r\n"
+            .to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+#[test]
+fn test_judge_echo_anonymity() {
+    let real_ip: IpAddr = "203.0.113.7".parse().unwrap();
+    let json = r#"{"ip": "198.51.100.2", "request_line": "GET / HTTP/1.1",
+        "headers": {"Host": "judge", "Via": "1.1 squid"}}"#;
+    let echo = JudgeEcho::parse(json);
+    assert_eq!(echo.ip, Some("198.51.100.2".parse().unwrap()));
+    assert_eq!(echo.header("via"), Some("1.1 squid"));
+    assert_eq!(echo.anonymity(Some(real_ip)), Anonymity::Anonymous);
+    let listing =
+        "REMOTE_ADDR = 198.51.100.2\nHTTP_HOST = judge\nHTTP_X_FORWARDED_FOR = 203.0.113.7\n";
+    assert_eq!(
+        JudgeEcho::parse(listing).anonymity(Some(real_ip)),
+        Anonymity::Transparent
+    );
+    let elite = "REMOTE_ADDR = 198.51.100.2\nHTTP_HOST = judge\n";
+    assert_eq!(
+        JudgeEcho::parse(elite).anonymity(Some(real_ip)),
+        Anonymity::Elite
+    );
+    let real_ip: IpAddr = "1.2.3.4".parse().unwrap();
+    let lookalike = "REMOTE_ADDR = 198.51.100.2\nHTTP_X_FORWARDED_FOR = 11.2.3.45, 10.1.2.3.4\n";
+    assert_eq!(
+        JudgeEcho::parse(lookalike).anonymity(Some(real_ip)),
+        Anonymity::Anonymous
+    );
+    let forwarded = r#"{"ip": "198.51.100.2",
+        "headers": {"Forwarded": "for=\"1.2.3.4:4711\";proto=http, for=198.51.100.9"}}"#;
+    assert_eq!(
+        JudgeEcho::parse(forwarded).anonymity(Some(real_ip)),
+        Anonymity::Transparent
+    );
+}
+
+#[tokio::test]
+async fn test_run_judge_echoes_request() {
+    use async_std::net::TcpStream;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    async_std::task::spawn(run_judge(listener, None));
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /judge HTTP/1.1\r\nHost: judge\r\nVia: 1.1 squid\r\n\r\n")
+        .await
+        .unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await.unwrap();
+    let (head, body) = answer.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    let echo = JudgeEcho::parse(body);
+    assert_eq!(echo.ip, Some("127.0.0.1".parse().unwrap()));
+    assert_eq!(echo.request_line.as_deref(), Some("GET /judge HTTP/1.1"));
+    assert_eq!(echo.header("via"), Some("1.1 squid"));
+}
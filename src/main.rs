@@ -1,50 +1,263 @@
-use clap::{arg, command, ArgGroup, ColorChoice, Command};
-use open_proxies::{concurrent_threads, readfile};
+use clap::{arg, command, Arg, ArgAction, ArgGroup, ArgMatches, ColorChoice, Command};
+use open_proxies::{
+    concurrent_threads, detect_real_ip, expand_hosts, extract_lists, judge_tls_acceptor,
+    parse_pac_rules, read_lists, run_judge, Anonymity, CertPolicy, CheckConfig, CsvColumn,
+    HostFilter, HttpMode, OutputConfig, OutputFormat, ParsedList, Proxy, Resolver, TargetAddr,
+    TargetUrl, VerifyConfig,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() {
-    let matches = cli().get_matches();
-    let inputfile = matches.get_one::<String>("input");
-    let outfile = match matches.get_one::<String>("out") {
+    let root = cli().get_matches();
+    if let Some(matches) = root.subcommand_matches("judge") {
+        judge(matches).await;
+        return;
+    }
+    if let Some(matches) = root.subcommand_matches("extract") {
+        extract(matches);
+        return;
+    }
+    let matches = root.subcommand_matches("check").unwrap_or(&root);
+    let inputs = matches.get_many::<String>("input").map(|inputs| inputs.cloned().collect::<Vec<_>>());
+    let mut outfile = match matches.get_one::<String>("out") {
         Some(m) => m.clone(),
         None => "live.txt".to_string(),
     };
+    if matches.get_flag("split") && !outfile.contains("{proto}") {
+        outfile = split_path(&outfile);
+    }
     let timeout = match matches.get_one::<u64>("timeout") {
-        None => 2 as u64,
-        Some(m) => m.clone(),
+        None => 2_u64,
+        Some(m) => *m,
     };
     let threads = match matches.get_one::<u64>("threads") {
-        None => 10 as u64,
-        Some(m) => m.clone(),
+        None => 10_u64,
+        Some(m) => *m,
     };
     let retry = match matches.get_one::<u64>("retrys") {
-        Some(m) => m.clone(),
-        None => 2 as u64,
+        Some(m) => *m,
+        None => 2_u64,
     };
-    match inputfile {
-        Some(input) => {
-            let inp = input.clone();
-            let proxies = readfile(inp).await;
-            if proxies.is_some() {
-                println!("🔥 start computing! 🔥");
-                concurrent_threads(Some(threads as usize), proxies.unwrap().clone(), timeout, retry as usize, Some(outfile)).await
+    let pac_rules = match matches.get_one::<String>("pac-rules") {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| parse_pac_rules(&text).map_err(|e| e.to_string()))
+        {
+            Ok(rules) => rules,
+            Err(e) => {
+                println!("could not load pac rules from {}: {}", path, e);
+                return;
             }
         },
+        None => vec![],
+    };
+    let output = OutputConfig {
+        path: Some(outfile),
+        sort_by_latency: matches.get_flag("sort"),
+        max_latency: matches
+            .get_one::<u64>("max-latency")
+            .map(|ms| Duration::from_millis(*ms)),
+        limit: matches.get_one::<usize>("top").copied(),
+        min_anonymity: matches.get_one::<Anonymity>("min-anonymity").copied(),
+        unique_exit_ip: matches.get_flag("unique-exit"),
+        format: matches.get_one::<OutputFormat>("format").copied().unwrap_or_default(),
+        all_results: matches.get_flag("all-results"),
+        group: matches.get_one::<String>("group").cloned(),
+        pac_rules,
+        csv_columns: matches.get_many::<CsvColumn>("columns").map(|columns| columns.copied().collect()).unwrap_or_default(),
+    };
+    let mut config = CheckConfig::default();
+    if let Some(target) = matches.get_one::<TargetAddr>("target") {
+        config.target = target.clone();
+    }
+    if let Some(url) = matches.get_one::<TargetUrl>("verify-url") {
+        let mut verify = VerifyConfig::new(url.clone());
+        verify.status = matches.get_one::<u16>("expect-status").copied();
+        verify.body = matches.get_one::<String>("expect-body").cloned();
+        config.verify = Some(verify);
+    }
+    config.resolver = Resolver::new(matches.get_one::<SocketAddr>("dns").copied());
+    config.proxy_tls.sni = matches.get_one::<String>("proxy-sni").cloned();
+    if let Some(mode) = matches.get_one::<HttpMode>("http-mode") {
+        config.http_mode = *mode;
+    }
+    if let Some(policy) = matches.get_one::<CertPolicy>("proxy-certs") {
+        config.proxy_tls.certificates = *policy;
+    }
+    if let Some(judge) = matches.get_one::<TargetUrl>("judge") {
+        config.judge = Some(judge.clone());
+        config.real_ip = match matches.get_one::<IpAddr>("real-ip") {
+            Some(ip) => Some(*ip),
+            None => match detect_real_ip(judge, timeout).await {
+                Ok(ip) => Some(ip),
+                Err(e) => {
+                    println!("could not detect real ip from judge: {}", e);
+                    None
+                }
+            },
+        };
+    }
+    match inputs {
+        Some(inputs) => {
+            let filter = matches.get_one::<HostFilter>("reject").copied().unwrap_or_default();
+            let list = match read_lists(&inputs, &filter) {
+                Ok(list) => list,
+                Err(e) => {
+                    println!("could not read input: {}", e);
+                    return;
+                }
+            };
+            if list.duplicates > 0 {
+                let total = list.proxies.len() + list.duplicates;
+                println!("♻️ {} of {} entries were duplicates, merged by host:port", list.duplicates, total);
+            }
+            report_rejected(&list, matches.get_one::<String>("rejects"));
+            if !list.proxies.is_empty() {
+                let mut proxies = list.proxies;
+                if matches.get_flag("expand-hosts") {
+                    proxies = expand_hosts(proxies, &config.resolver).await;
+                }
+                println!("🔥 start computing! 🔥");
+                concurrent_threads(
+                    Some(threads as usize),
+                    proxies,
+                    timeout,
+                    retry as usize,
+                    &output,
+                    &config,
+                )
+                .await;
+            }
+        }
         None => {
             println!("no inputfile in args!");
         }
     };
 }
-fn cli() -> Command {
-    command!()
-        .args([
-            arg!(-i --input <FILENAME> "TXT file path where proxies ready to be parsed").group("options")
+/// `out/live.txt` turned into `out/{proto}.txt`.
+fn split_path(path: &str) -> String {
+    let path = std::path::Path::new(path);
+    let name = match path.extension() {
+        Some(extension) => format!("{{proto}}.{}", extension.to_string_lossy()),
+        None => "{proto}".to_string(),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+fn report_rejected(list: &ParsedList, rejects: Option<&String>) {
+    if list.rejected.is_empty() {
+        return;
+    }
+    let total = list.proxies.len() + list.duplicates + list.rejected.len();
+    println!("⚠️ {} of {} lines rejected", list.rejected.len(), total);
+    for error in list.rejected.iter().take(5) {
+        println!("   {}", error);
+    }
+    match rejects {
+        Some(path) => {
+            let lines = list.rejected.iter().map(|error| format!("{}\n", error)).collect::<String>();
+            match std::fs::write(path, lines) {
+                Ok(()) => println!("   all rejected lines written to {}", path),
+                Err(e) => println!("   could not write {}: {}", path, e),
+            }
+        }
+        None if list.rejected.len() > 5 => println!("   ... pass --rejects <FILENAME> to collect them all"),
+        None => {}
+    }
+}
+fn extract(matches: &ArgMatches) {
+    let inputs = matches.get_many::<String>("input").unwrap().cloned().collect::<Vec<_>>();
+    let filter = matches.get_one::<HostFilter>("reject").copied().unwrap_or_default();
+    let list = match extract_lists(&inputs, &filter) {
+        Ok(list) => list,
+        Err(e) => {
+            eprintln!("could not read input: {}", e);
+            return;
+        }
+    };
+    let lines = list
+        .proxies
+        .iter()
+        .flat_map(Proxy::list_lines)
+        .map(|line| line + "\n")
+        .collect::<String>();
+    match matches.get_one::<String>("out") {
+        Some(path) => match std::fs::write(path, lines) {
+            Ok(()) => eprintln!("🔎 {} proxies extracted to {}", list.proxies.len(), path),
+            Err(e) => eprintln!("could not write {}: {}", path, e),
+        },
+        None => print!("{}", lines),
+    }
+    if list.duplicates > 0 {
+        eprintln!("♻️ {} duplicate entries merged by host:port", list.duplicates);
+    }
+    if !list.rejected.is_empty() {
+        eprintln!("⚠️ {} proxies left out by --reject", list.rejected.len());
+    }
+}
+async fn judge(matches: &ArgMatches) {
+    let mut servers = vec![];
+    if let Some(addr) = matches.get_one::<String>("listen") {
+        match async_std::net::TcpListener::bind(addr.as_str()).await {
+            Ok(listener) => {
+                println!("⚖️ judge listening on http://{}", addr);
+                servers.push(async_std::task::spawn(run_judge(listener, None)));
+            }
+            Err(e) => println!("could not listen on {}: {}", addr, e),
+        }
+    }
+    if let Some(addr) = matches.get_one::<String>("tls-listen") {
+        let cert = matches.get_one::<String>("cert").unwrap();
+        let key = matches.get_one::<String>("key").unwrap();
+        match judge_tls_acceptor(cert, key) {
+            Ok(acceptor) => match async_std::net::TcpListener::bind(addr.as_str()).await {
+                Ok(listener) => {
+                    println!("⚖️ judge listening on https://{}", addr);
+                    servers.push(async_std::task::spawn(run_judge(listener, Some(acceptor))));
+                }
+                Err(e) => println!("could not listen on {}: {}", addr, e),
+            },
+            Err(e) => println!("could not load tls certificate: {}", e),
+        }
+    }
+    for server in servers {
+        if let Err(e) = server.await {
+            println!("judge stopped: {}", e);
+        }
+    }
+}
+fn check_args() -> Vec<Arg> {
+    vec![
+            arg!(-i --input <FILENAME> "TXT file, glob or - for stdin where proxies ready to be parsed (repeatable)").group("options")
             .value_parser(clap::builder::NonEmptyStringValueParser::new())
+            .action(ArgAction::Append)
             .required(true),
-            arg!(-o --out <FILENAME> "file path where live proxies will be saved").group("options")
+            arg!(-o --out <FILENAME> "file path where live proxies will be saved, {proto} in it splits them per protocol").group("options")
             .default_value("live.txt")
             .value_parser(clap::builder::NonEmptyStringValueParser::new())
             .required(false),
+            arg!(--format <FORMAT> "how results are written to --out: text|csv (live proxies), json|jsonl (every result) or proxychains|clash|sing-box|pac").group("options")
+            .default_value("text")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<OutputFormat>()))
+            .required(false),
+            arg!(--split "write a file per protocol next to --out: http.txt, https.txt, socks4.txt, socks5.txt").group("options")
+            .required(false),
+            arg!(--"all-results" "write dead proxies to --out too, not only the live ones").group("options")
+            .required(false),
+            arg!(--columns <NAMES> "comma separated csv columns [default: protocol,host,port,alive,latency_ms,error,anonymity,country,checked_at]").group("options")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<CsvColumn>()))
+            .value_delimiter(',')
+            .required(false),
+            arg!(--group <NAME> "with a proxychains|clash|sing-box --format, also define a load-balancing group of this name").group("options")
+            .value_parser(clap::builder::NonEmptyStringValueParser::new())
+            .required(false),
+            arg!(--"pac-rules" <FILENAME> "with --format pac, lines of host pattern and route (direct, proxy or a protocol) checked first").group("options")
+            .value_parser(clap::builder::NonEmptyStringValueParser::new())
+            .required(false),
+            arg!(--rejects <FILENAME> "file collecting the input lines that could not be loaded, with the reason").group("options")
+            .value_parser(clap::builder::NonEmptyStringValueParser::new())
+            .required(false),
             arg!(-t --timeout <NUMBER> "single proxy compute iteration timeout in seconds").group("options")
             .default_value("2")
             .value_parser(clap::value_parser!(u64).range(1..5))
@@ -58,13 +271,115 @@ fn cli() -> Command {
             .value_parser(clap::builder::NonEmptyStringValueParser::new())
             .value_parser(clap::value_parser!(u64).range(1..4))
             .required(false),
-        ])
+            arg!(--target <HOST_PORT> "destination proxies are asked to CONNECT to").group("options")
+            .default_value("1.1.1.1:80")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<TargetAddr>()))
+            .required(false),
+            arg!(--"verify-url" <URL> "http(s) url fetched through every proxy to verify it (replaces --target)").group("options")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<TargetUrl>()))
+            .required(false),
+            arg!(--"expect-status" <CODE> "status code --verify-url must answer with [default: any 2xx]").group("options")
+            .value_parser(clap::value_parser!(u16))
+            .requires("verify-url")
+            .required(false),
+            arg!(--"expect-body" <TEXT> "text the --verify-url response body must contain").group("options")
+            .value_parser(clap::builder::NonEmptyStringValueParser::new())
+            .requires("verify-url")
+            .required(false),
+            arg!(--reject <RANGES> "leave out proxies in these ranges: private,loopback,multicast,reserved,bogon or all").group("options")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<HostFilter>()))
+            .required(false),
+            arg!(--dns <ADDR> "DNS server resolving hostname proxies, as ip or ip:port [default: system resolver]").group("options")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| {
+                s.parse::<SocketAddr>().or_else(|_| s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+            }))
+            .required(false),
+            arg!(--"expand-hosts" "check every A/AAAA record of hostname proxies as its own proxy").group("options")
+            .required(false),
+            arg!(--"http-mode" <MODE> "requests http(s) proxies are tried with: connect|forward|both").group("options")
+            .default_value("connect")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<HttpMode>()))
+            .required(false),
+            arg!(--"proxy-sni" <NAME> "server name sent to https proxies [default: the proxy host, none for ips]").group("options")
+            .value_parser(clap::builder::NonEmptyStringValueParser::new())
+            .required(false),
+            arg!(--"proxy-certs" <POLICY> "how https proxy certificates are checked: verify|accept-invalid").group("options")
+            .default_value("verify")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<CertPolicy>()))
+            .required(false),
+            arg!(--judge <URL> "judge url fetched through live proxies for their exit ip and anonymity").group("options")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<TargetUrl>()))
+            .required(false),
+            arg!(--"real-ip" <IP> "our public ip, asked from --judge directly when not given").group("options")
+            .value_parser(clap::value_parser!(IpAddr))
+            .requires("judge")
+            .required(false),
+            arg!(--"min-anonymity" <LEVEL> "leave out proxies rated below transparent|anonymous|elite").group("options")
+            .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<Anonymity>()))
+            .requires("judge")
+            .required(false),
+            arg!(--"unique-exit" "keep only the fastest of the proxies sharing an exit ip").group("options")
+            .requires("judge")
+            .required(false),
+            arg!(--sort "write live proxies fastest first").group("options")
+            .required(false),
+            arg!(--"max-latency" <MS> "leave out live proxies slower than this many milliseconds").group("options")
+            .value_parser(clap::value_parser!(u64))
+            .required(false),
+            arg!(--top <N> "keep only the N first (fastest with --sort) live proxies").group("options")
+            .value_parser(clap::value_parser!(usize))
+            .required(false),
+    ]
+}
+fn cli() -> Command {
+    command!()
+        .args(check_args())
+        .subcommand(
+            Command::new("check")
+            .about("check proxy lists, the same as running without a subcommand")
+            .args(check_args())
+            .group(ArgGroup::new("options").multiple(true))
+        )
+        .subcommand(
+            Command::new("extract")
+            .about("pull ip:port pairs out of pasted web pages, forum posts or JSON and print them as a list")
+            .args([
+                arg!(-i --input <FILENAME> "text or HTML file, glob or - for stdin (repeatable)")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .action(ArgAction::Append)
+                .required(true),
+                arg!(-o --out <FILENAME> "file path where extracted proxies will be saved [default: stdout]")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                arg!(--reject <RANGES> "leave out proxies in these ranges: private,loopback,multicast,reserved,bogon or all")
+                .value_parser(clap::builder::ValueParser::new(|s: &str| s.parse::<HostFilter>())),
+            ])
+        )
+        .subcommand(
+            Command::new("judge")
+            .about("run a judge echoing every request's ip, headers and request line as JSON")
+            .args([
+                arg!(--listen <ADDR> "address the http judge listens on")
+                .default_value("0.0.0.0:8080")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                arg!(--"tls-listen" <ADDR> "address the https judge listens on")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .requires_all(["cert", "key"]),
+                arg!(--cert <FILENAME> "PEM certificate chain for --tls-listen")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+                arg!(--key <FILENAME> "PEM private key for --tls-listen")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+            ])
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
         .group(ArgGroup::new("options").multiple(true))
         .group(ArgGroup::new("usage").multiple(true))
         .next_help_heading("USAGE")
         .args([
             arg!(-a <example1> "open_proxies -i ./socks.txt -o ./live.txt -t 2 -r 2 -n 10").group("usage"),
             arg!(-b <example2> "open_proxies -i ./socks.txt -o ./live.txt").group("usage"),
+            arg!(-c <example3> "cat lists/*.txt | open_proxies check -i -").group("usage"),
+            arg!(-d <example4> "open_proxies extract -i page.html | open_proxies check -i -").group("usage"),
         ])
         .about(r"███╗░░░███╗░█████╗░██████╗░███████╗  ░██╗░░░░░░░██╗██╗████████╗██╗░░██╗  ██╗░░░░░░█████╗░██╗░░░██╗███████╗
         ████╗░████║██╔══██╗██╔══██╗██╔════╝ ░██║░░██╗░░██║██║╚══██╔══╝██║░░██║  ██║░░░░░██╔══██╗██║░░░██║██╔════╝
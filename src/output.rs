@@ -0,0 +1,709 @@
+use crate::export::{clash_config, pac_file, proxychains_config, sing_box_config};
+use crate::{Anonymity, CheckResult, PacRule, Proto, Proxy};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// How results are written to the output file.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    /// One URI per live proxy, as read back by `readfile`.
+    #[default]
+    Text,
+    /// A JSON array holding a record for every tested proxy, dead ones
+    /// included, written once the run is over.
+    Json,
+    /// The same records, one per line, written as checks finish.
+    Jsonl,
+    /// A spreadsheet row per proxy under a header naming the columns.
+    Csv,
+    /// A proxychains configuration with the live proxies as its
+    /// `[ProxyList]`, fastest first.
+    Proxychains,
+    /// A Clash `proxies` list, fastest first.
+    Clash,
+    /// sing-box outbounds, fastest first.
+    SingBox,
+    /// A PAC file failing over from the fastest proxy to the slowest.
+    Pac,
+}
+impl OutputFormat {
+    /// Whether the format is a client configuration, written once the run
+    /// is over from the live proxies only.
+    fn is_config(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Proxychains
+                | OutputFormat::Clash
+                | OutputFormat::SingBox
+                | OutputFormat::Pac
+        )
+    }
+}
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::SingBox => write!(f, "sing-box"),
+            _ => write!(f, "{}", format!("{:?}", self).to_lowercase()),
+        }
+    }
+}
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::Jsonl),
+            "csv" => Ok(OutputFormat::Csv),
+            "proxychains" => Ok(OutputFormat::Proxychains),
+            "clash" => Ok(OutputFormat::Clash),
+            "sing-box" | "singbox" => Ok(OutputFormat::SingBox),
+            "pac" => Ok(OutputFormat::Pac),
+            _ => Err(format!("unknown output format \"{}\"", s)),
+        }
+    }
+}
+
+/// A column of CSV output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CsvColumn {
+    Protocol,
+    Host,
+    Port,
+    Alive,
+    LatencyMs,
+    /// Stage and reason of the failure, empty for live proxies.
+    Error,
+    Anonymity,
+    /// Left empty, nothing looks up where proxies are yet.
+    Country,
+    CheckedAt,
+    /// The proxy URI, credentials included.
+    Proxy,
+    ExitIp,
+    /// Address the proxy was reached at, its host once resolved.
+    ConnectIp,
+    /// Whether the exit ip differs from the address connected to.
+    ExitsElsewhere,
+    HttpSupport,
+}
+impl CsvColumn {
+    /// The columns written unless others are asked for, in this order.
+    pub const DEFAULT: [CsvColumn; 9] = [
+        CsvColumn::Protocol,
+        CsvColumn::Host,
+        CsvColumn::Port,
+        CsvColumn::Alive,
+        CsvColumn::LatencyMs,
+        CsvColumn::Error,
+        CsvColumn::Anonymity,
+        CsvColumn::Country,
+        CsvColumn::CheckedAt,
+    ];
+    fn value(&self, result: &CheckResult) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        match self {
+            CsvColumn::Protocol => result.proto.to_string(),
+            CsvColumn::Host => result.proxy.host.to_string(),
+            CsvColumn::Port => result.proxy.port.to_string(),
+            CsvColumn::Alive => result.is_alive().to_string(),
+            CsvColumn::LatencyMs => optional(
+                result
+                    .is_alive()
+                    .then(|| result.latency().as_millis().to_string()),
+            ),
+            CsvColumn::Error => optional(result.error.as_ref().map(|error| error.to_string())),
+            CsvColumn::Anonymity => {
+                optional(result.anonymity.map(|anonymity| anonymity.to_string()))
+            }
+            CsvColumn::Country => String::new(),
+            CsvColumn::CheckedAt => rfc3339(result.checked_at),
+            CsvColumn::Proxy => result.proxy.to_string(),
+            CsvColumn::ExitIp => optional(result.exit_ip.map(|ip| ip.to_string())),
+            CsvColumn::ConnectIp => optional(result.connect_ip.map(|ip| ip.to_string())),
+            CsvColumn::ExitsElsewhere => result.exits_elsewhere().to_string(),
+            CsvColumn::HttpSupport => {
+                optional(result.http_support.map(|support| support.to_string()))
+            }
+        }
+    }
+}
+impl fmt::Display for CsvColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CsvColumn::Protocol => "protocol",
+            CsvColumn::Host => "host",
+            CsvColumn::Port => "port",
+            CsvColumn::Alive => "alive",
+            CsvColumn::LatencyMs => "latency_ms",
+            CsvColumn::Error => "error",
+            CsvColumn::Anonymity => "anonymity",
+            CsvColumn::Country => "country",
+            CsvColumn::CheckedAt => "checked_at",
+            CsvColumn::Proxy => "proxy",
+            CsvColumn::ExitIp => "exit_ip",
+            CsvColumn::ConnectIp => "connect_ip",
+            CsvColumn::ExitsElsewhere => "exits_elsewhere",
+            CsvColumn::HttpSupport => "http_support",
+        };
+        write!(f, "{}", name)
+    }
+}
+impl FromStr for CsvColumn {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        [
+            &CsvColumn::DEFAULT[..],
+            &[
+                CsvColumn::Proxy,
+                CsvColumn::ExitIp,
+                CsvColumn::ConnectIp,
+                CsvColumn::ExitsElsewhere,
+                CsvColumn::HttpSupport,
+            ],
+        ]
+        .concat()
+        .into_iter()
+        .find(|column| column.to_string() == name)
+        .ok_or_else(|| format!("unknown csv column \"{}\"", s))
+    }
+}
+
+/// Where live proxies are written and which of them make it there.
+#[derive(Default, Clone, Debug)]
+pub struct OutputConfig {
+    /// Output file, `live.txt` in the working directory when unset. A
+    /// `{proto}` in the path splits the output into a file per protocol,
+    /// e.g. `out/{proto}.txt` gives `out/http.txt`, `out/socks5.txt`...
+    pub path: Option<String>,
+    /// Write the fastest proxies first instead of in completion order.
+    pub sort_by_latency: bool,
+    /// Leave out live proxies slower than this.
+    pub max_latency: Option<Duration>,
+    /// Keep only the first `n` proxies, after sorting.
+    pub limit: Option<usize>,
+    /// Leave out proxies rated below this level, and unrated HTTP(S) ones;
+    /// SOCKS proxies are never rated and kept.
+    pub min_anonymity: Option<Anonymity>,
+    /// Keep only the fastest proxy of those sharing an exit ip.
+    pub unique_exit_ip: bool,
+    /// How the output file is written.
+    pub format: OutputFormat,
+    /// Write every tested proxy, dead ones included, instead of the live
+    /// ones the filters above keep. Always on for the JSON formats, never
+    /// for configurations.
+    pub all_results: bool,
+    /// Columns of CSV output, `CsvColumn::DEFAULT` when empty.
+    pub csv_columns: Vec<CsvColumn>,
+    /// Name of a load-balancing group over all proxies of a configuration,
+    /// none is added when unset.
+    pub group: Option<String>,
+    /// Host pattern routes checked before the proxies of a PAC file.
+    pub pac_rules: Vec<PacRule>,
+}
+impl OutputConfig {
+    /// Whether `result` passes the liveness, latency and anonymity filters.
+    pub fn keeps(&self, result: &CheckResult) -> bool {
+        result.is_alive()
+            && self
+                .max_latency
+                .is_none_or(|max_latency| result.latency() <= max_latency)
+            && match (self.min_anonymity, result.anonymity) {
+                (Some(min), Some(anonymity)) => anonymity >= min,
+                // SOCKS proxies are never rated, unrated HTTP(S) ones may leak
+                (Some(_), None) => !matches!(result.proto, Proto::HTTP | Proto::HTTPS),
+                (None, _) => true,
+            }
+    }
+    /// Whether every result is written rather than the live ones kept.
+    fn all(&self) -> bool {
+        (self.all_results && !self.format.is_config())
+            || matches!(self.format, OutputFormat::Json | OutputFormat::Jsonl)
+    }
+    /// Whether `result` is written, before sorting and limits.
+    pub(crate) fn lists(&self, result: &CheckResult) -> bool {
+        self.all() || self.keeps(result)
+    }
+    /// Whether results can be written as they come in, or have to wait for
+    /// the whole run to be ranked.
+    pub(crate) fn streams(&self) -> bool {
+        let ranked = match self.all() {
+            true => self.sort_by_latency,
+            false => self.sort_by_latency || self.limit.is_some() || self.unique_exit_ip,
+        };
+        !ranked && self.format != OutputFormat::Json && !self.format.is_config()
+    }
+    /// First line of the output file, for formats that have one.
+    pub(crate) fn header(&self) -> Option<String> {
+        (self.format == OutputFormat::Csv).then(|| {
+            let names = self.columns().iter().map(|column| column.to_string());
+            format!("{}\n", names.collect::<Vec<_>>().join(","))
+        })
+    }
+    /// `result` as a line of the output file, for formats written line by
+    /// line.
+    pub(crate) fn line(&self, result: &CheckResult) -> String {
+        match self.format {
+            OutputFormat::Csv => format!("{}\n", csv_row(result, self.columns())),
+            OutputFormat::Json | OutputFormat::Jsonl => json_line(result),
+            _ => text_line(&result.proxy),
+        }
+    }
+    /// The whole contents of an output file holding `results`, header
+    /// aside.
+    pub(crate) fn document(&self, results: &[&CheckResult]) -> String {
+        let group = self.group.as_deref();
+        match self.format {
+            OutputFormat::Json => {
+                let records = results.iter().map(|result| json_record(result));
+                let json = serde_json::to_string_pretty(&records.collect::<Vec<_>>());
+                format!("{}\n", json.unwrap_or_default())
+            }
+            OutputFormat::Proxychains => proxychains_config(results, group),
+            OutputFormat::Clash => clash_config(results, group),
+            OutputFormat::SingBox => sing_box_config(results, group),
+            OutputFormat::Pac => pac_file(results, &self.pac_rules),
+            _ => results.iter().map(|result| self.line(result)).collect(),
+        }
+    }
+    /// Whether results are split into a file per protocol.
+    pub fn splits(&self) -> bool {
+        self.path
+            .as_deref()
+            .is_some_and(|path| path.contains("{proto}"))
+    }
+    /// File the results of `proto` are written to.
+    pub fn path_for(&self, proto: &Proto) -> String {
+        self.path
+            .as_deref()
+            .unwrap_or("live.txt")
+            .replace("{proto}", &proto.to_string())
+    }
+    fn columns(&self) -> &[CsvColumn] {
+        match self.csv_columns.is_empty() {
+            true => &CsvColumn::DEFAULT,
+            false => &self.csv_columns,
+        }
+    }
+}
+
+/// The files of a run, each opened with its header on first write.
+pub(crate) struct OutputFiles<'a> {
+    output: &'a OutputConfig,
+    files: Mutex<HashMap<String, File>>,
+}
+impl<'a> OutputFiles<'a> {
+    /// Creates the output file, or one per protocol when splitting so no
+    /// stale file of an earlier run is left behind.
+    pub(crate) fn create(output: &'a OutputConfig) -> io::Result<Self> {
+        let files = OutputFiles {
+            output,
+            files: Mutex::new(HashMap::new()),
+        };
+        match output.splits() {
+            true => {
+                for proto in [Proto::HTTP, Proto::HTTPS, Proto::SOCKS4, Proto::SOCKS5] {
+                    files.append(&proto, "")?;
+                }
+            }
+            false => files.append(&Proto::UNKNOWN, "")?,
+        }
+        Ok(files)
+    }
+    /// Appends `text` to the file of `proto`, creating the file first.
+    fn append(&self, proto: &Proto, text: &str) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let file = match files.entry(self.output.path_for(proto)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = Path::new(entry.key());
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let mut file = File::create(path)?;
+                if let Some(header) = self.output.header() {
+                    file.write_all(header.as_bytes())?;
+                }
+                entry.insert(file)
+            }
+        };
+        file.write_all(text.as_bytes())
+    }
+    pub(crate) fn write(&self, proto: &Proto, text: &str) {
+        let _ = self.append(proto, text);
+    }
+    /// Writes `selected` at once, into the file of their protocol. Without
+    /// a split the single file gets a document even when nothing was
+    /// selected, such as an empty JSON array.
+    pub(crate) fn write_all(&self, selected: &[&CheckResult]) {
+        let mut documents: Vec<(Proto, Vec<&CheckResult>)> = vec![];
+        if !self.output.splits() {
+            documents.push((Proto::UNKNOWN, vec![]));
+        }
+        for result in selected {
+            let path = self.output.path_for(&result.proto);
+            match documents
+                .iter_mut()
+                .find(|(proto, _)| self.output.path_for(proto) == path)
+            {
+                Some((_, results)) => results.push(result),
+                None => documents.push((result.proto.clone(), vec![result])),
+            }
+        }
+        for (proto, results) in documents {
+            self.write(&proto, &self.output.document(&results));
+        }
+    }
+}
+
+/// Filters `results` down to the live proxies `output` keeps, fastest first
+/// when sorting is enabled.
+pub fn rank<'a>(results: &'a [CheckResult], output: &OutputConfig) -> Vec<&'a CheckResult> {
+    let mut live = results
+        .iter()
+        .filter(|result| output.keeps(result))
+        .collect::<Vec<_>>();
+    if output.unique_exit_ip {
+        let mut fastest: HashMap<IpAddr, &CheckResult> = HashMap::new();
+        for result in &live {
+            if let Some(exit_ip) = result.exit_ip {
+                let best = fastest.entry(exit_ip).or_insert(result);
+                if result.latency() < best.latency() {
+                    *best = result;
+                }
+            }
+        }
+        live.retain(|result| match result.exit_ip {
+            Some(exit_ip) => std::ptr::eq(fastest[&exit_ip], *result),
+            None => true,
+        });
+    }
+    if output.sort_by_latency {
+        live.sort_by_key(|result| result.latency());
+    }
+    if let Some(limit) = output.limit {
+        live.truncate(limit);
+    }
+    live
+}
+
+/// The results `output` writes: every one, fastest first when sorting, or
+/// the live ones `rank` keeps.
+pub fn select<'a>(results: &'a [CheckResult], output: &OutputConfig) -> Vec<&'a CheckResult> {
+    if !output.all() {
+        return rank(results, output);
+    }
+    let mut selected = results.iter().collect::<Vec<_>>();
+    if output.sort_by_latency {
+        selected.sort_by_key(|result| (!result.is_alive(), result.latency()));
+    }
+    selected
+}
+
+/// Live proxies sharing an exit ip, largest groups first.
+pub fn group_by_exit_ip(results: &[CheckResult]) -> Vec<(IpAddr, Vec<&CheckResult>)> {
+    let mut groups: HashMap<IpAddr, Vec<&CheckResult>> = HashMap::new();
+    for result in results.iter().filter(|result| result.is_alive()) {
+        if let Some(exit_ip) = result.exit_ip {
+            groups.entry(exit_ip).or_default().push(result);
+        }
+    }
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+    groups
+}
+
+/// Latency, anonymity and exit ip of a live proxy, for the console.
+pub(crate) fn live_summary(result: &CheckResult) -> String {
+    let mut summary = format!("{:?}", result.latency());
+    if let Some(http_support) = result.http_support {
+        summary.push_str(&format!(" {}", http_support));
+    }
+    if let Some(anonymity) = result.anonymity {
+        summary.push_str(&format!(" {}", anonymity));
+    }
+    if let Some(exit_ip) = result.exit_ip {
+        let flag = if result.exits_elsewhere() {
+            " ⚠️"
+        } else {
+            ""
+        };
+        summary.push_str(&format!(" exit {}{}", exit_ip, flag));
+    }
+    summary
+}
+
+/// A proxy's URI line, as read back by `readfile`.
+pub(crate) fn text_line(proxy: &Proxy) -> String {
+    format!("{}\n", proxy)
+}
+
+/// Everything known about a checked proxy, dead or alive, as written by
+/// the JSON formats.
+pub fn json_record(result: &CheckResult) -> serde_json::Value {
+    let ms = |elapsed: Duration| elapsed.as_secs_f64() * 1000.0;
+    let timings: serde_json::Map<String, serde_json::Value> = result
+        .timings
+        .iter()
+        .map(|timing| (timing.stage.to_string(), ms(timing.elapsed).into()))
+        .collect();
+    serde_json::json!({
+        "proxy": result.proxy.to_string(),
+        "protocol": result.proto.to_string(),
+        "host": result.proxy.host.to_string(),
+        "port": result.proxy.port,
+        "alive": result.is_alive(),
+        "latency_ms": result.is_alive().then(|| ms(result.latency())),
+        "stage": result.stage.to_string(),
+        "error": result.error.as_ref().map(|error| serde_json::json!({
+            "stage": error.stage.to_string(),
+            "kind": format!("{:?}", error.kind),
+            "message": error.message,
+        })),
+        "timings_ms": timings,
+        "checked_at": rfc3339(result.checked_at),
+        "anonymity": result.anonymity.map(|anonymity| anonymity.to_string()),
+        "exit_ip": result.exit_ip.map(|ip| ip.to_string()),
+        "connect_ip": result.connect_ip.map(|ip| ip.to_string()),
+        "exits_elsewhere": result.exits_elsewhere(),
+        "http_support": result.http_support.map(|support| support.to_string()),
+    })
+}
+
+/// `columns` of `result` as a CSV row, without the line ending. Fields
+/// holding commas, quotes or line breaks are quoted.
+pub fn csv_row(result: &CheckResult, columns: &[CsvColumn]) -> String {
+    columns
+        .iter()
+        .map(|column| {
+            let value = column.value(result);
+            if value.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// `result` as a line of JSON Lines output.
+pub(crate) fn json_line(result: &CheckResult) -> String {
+    format!("{}\n", json_record(result))
+}
+
+/// `time` as an RFC 3339 UTC timestamp, to the second.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // civil date from days since the epoch, after Howard Hinnant's algorithm
+    let days = days + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[test]
+fn test_rank_sorts_filters_and_limits() {
+    use crate::{CheckError, ErrorKind, Proto, Stage};
+    let result = |port: u16, ms: u64, alive: bool| {
+        let mut result = CheckResult::for_test(Proxy::new(Proto::SOCKS5, "10.0.0.1", port), ms);
+        result.error = (!alive).then(|| CheckError {
+            stage: Stage::Connect,
+            kind: ErrorKind::Timeout,
+            message: "timed out".into(),
+        });
+        result
+    };
+    let results = vec![
+        result(1, 300, true),
+        result(2, 50, true),
+        result(3, 10, false),
+        result(4, 900, true),
+        result(5, 120, true),
+    ];
+    let output = OutputConfig {
+        sort_by_latency: true,
+        max_latency: Some(Duration::from_millis(500)),
+        limit: Some(2),
+        ..Default::default()
+    };
+    let ports = rank(&results, &output)
+        .iter()
+        .map(|result| result.proxy.port)
+        .collect::<Vec<_>>();
+    assert_eq!(ports, vec![2, 5]);
+}
+
+#[test]
+fn test_min_anonymity_drops_unrated_http() {
+    let output = OutputConfig {
+        min_anonymity: Some(Anonymity::Anonymous),
+        ..Default::default()
+    };
+    let mut http = CheckResult::for_test(Proxy::new(Proto::HTTP, "10.0.0.1", 8080), 10);
+    assert!(!output.keeps(&http));
+    http.anonymity = Some(Anonymity::Transparent);
+    assert!(!output.keeps(&http));
+    http.anonymity = Some(Anonymity::Elite);
+    assert!(output.keeps(&http));
+    let socks = CheckResult::for_test(Proxy::new(Proto::SOCKS5, "10.0.0.1", 1080), 10);
+    assert!(output.keeps(&socks));
+}
+
+#[test]
+fn test_unique_exit_ip_keeps_fastest() {
+    use crate::Proto;
+    let result = |port: u16, ms: u64, exit_ip: Option<&str>| {
+        let mut result = CheckResult::for_test(Proxy::new(Proto::HTTP, "10.0.0.1", port), ms);
+        result.exit_ip = exit_ip.map(|ip| ip.parse().unwrap());
+        result
+    };
+    let results = vec![
+        result(1, 300, Some("198.51.100.1")),
+        result(2, 50, Some("198.51.100.1")),
+        result(3, 80, Some("198.51.100.2")),
+        result(4, 10, None),
+        result(5, 120, Some("198.51.100.1")),
+    ];
+    let groups = group_by_exit_ip(&results);
+    assert_eq!(groups[0].0, "198.51.100.1".parse::<IpAddr>().unwrap());
+    assert_eq!(groups[0].1.len(), 3);
+    assert!(results[0].exits_elsewhere());
+    let output = OutputConfig {
+        unique_exit_ip: true,
+        ..Default::default()
+    };
+    let ports = rank(&results, &output)
+        .iter()
+        .map(|result| result.proxy.port)
+        .collect::<Vec<_>>();
+    assert_eq!(ports, vec![2, 3, 4]);
+}
+
+#[test]
+fn test_json_record_of_dead_proxy() {
+    use crate::{CheckError, ErrorKind, Proto, Stage};
+    let mut result = CheckResult::for_test(Proxy::new(Proto::SOCKS5, "10.0.0.1", 1080), 40);
+    result.stage = Stage::Greeting;
+    result.error = Some(CheckError {
+        stage: Stage::Greeting,
+        kind: ErrorKind::Timeout,
+        message: "timed out".into(),
+    });
+    // 2000-02-29, a leap day
+    result.checked_at = SystemTime::UNIX_EPOCH + Duration::from_secs(951_782_400 + 3_723);
+    let record = json_record(&result);
+    assert_eq!(record["proxy"], "socks5://10.0.0.1:1080");
+    assert_eq!(record["alive"], false);
+    assert!(record["latency_ms"].is_null());
+    assert_eq!(record["error"]["stage"], "greeting");
+    assert_eq!(record["error"]["kind"], "Timeout");
+    assert_eq!(record["timings_ms"]["tcp connect"], 40.0);
+    assert_eq!(record["checked_at"], "2000-02-29T01:02:03Z");
+    assert_eq!(record["connect_ip"], "10.0.0.1");
+    assert_eq!(record["exits_elsewhere"], false);
+    assert!(!json_line(&result).trim_end().contains('\n'));
+    assert_eq!("JSONL".parse::<OutputFormat>(), Ok(OutputFormat::Jsonl));
+}
+
+#[test]
+fn test_csv_header_columns_and_quoting() {
+    use crate::{CheckError, ErrorKind, Proto, Stage};
+    let mut result = CheckResult::for_test(Proxy::new(Proto::HTTP, "10.0.0.1", 8080), 0);
+    result.stage = Stage::Tunnel;
+    result.error = Some(CheckError {
+        stage: Stage::Tunnel,
+        kind: ErrorKind::BadStatus(403),
+        message: "proxy answered \"403 Forbidden\", closing".into(),
+    });
+    let mut output = OutputConfig {
+        format: OutputFormat::Csv,
+        ..Default::default()
+    };
+    assert_eq!(
+        output.header().unwrap(),
+        "protocol,host,port,alive,latency_ms,error,anonymity,country,checked_at\n"
+    );
+    assert_eq!(
+        output.line(&result),
+        "http,10.0.0.1,8080,false,,\"tunnel: proxy answered \"\"403 Forbidden\"\", closing\",,,\
+         1970-01-01T00:00:00Z\n"
+    );
+    assert!(!output.lists(&result));
+    output.all_results = true;
+    assert!(output.lists(&result));
+    output.csv_columns = "host, alive,connect_ip,exit_ip,exits_elsewhere"
+        .split(',')
+        .map(|name| name.parse().unwrap())
+        .collect();
+    result.error = None;
+    result.exit_ip = Some("198.51.100.3".parse().unwrap());
+    assert_eq!(
+        output.header().unwrap(),
+        "host,alive,connect_ip,exit_ip,exits_elsewhere\n"
+    );
+    assert_eq!(
+        output.line(&result),
+        "10.0.0.1,true,10.0.0.1,198.51.100.3,true\n"
+    );
+    assert!("ping".parse::<CsvColumn>().is_err());
+}
+
+#[test]
+fn test_output_files_split_per_protocol() {
+    let dir = std::env::temp_dir().join(format!("open_proxies_split_{}", std::process::id()));
+    let output = OutputConfig {
+        path: Some(dir.join("{proto}.txt").to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+    assert!(output.splits());
+    let files = OutputFiles::create(&output).unwrap();
+    for (proto, port) in [
+        (Proto::SOCKS5, 1080),
+        (Proto::HTTP, 8080),
+        (Proto::SOCKS5, 1081),
+    ] {
+        let result = CheckResult::for_test(Proxy::new(proto, "10.0.0.1", port), 10);
+        files.write(&result.proto, &output.line(&result));
+    }
+    drop(files);
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(
+        read("socks5.txt"),
+        "socks5://10.0.0.1:1080\nsocks5://10.0.0.1:1081\n"
+    );
+    assert_eq!(read("http.txt"), "http://10.0.0.1:8080\n");
+    assert_eq!(read("socks4.txt"), "");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,503 @@
+use crate::check::{Stage, Trace};
+use crate::Credentials;
+use async_std::io::{ReadExt, WriteExt};
+use futures::{AsyncRead, AsyncWrite};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+/// Destination a proxy is asked to open a tunnel to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TargetAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+impl TargetAddr {
+    pub fn port(&self) -> u16 {
+        match self {
+            TargetAddr::Ip(addr) => addr.port(),
+            TargetAddr::Domain(_, port) => *port,
+        }
+    }
+    pub fn host(&self) -> String {
+        match self {
+            TargetAddr::Ip(addr) => addr.ip().to_string(),
+            TargetAddr::Domain(host, _) => host.clone(),
+        }
+    }
+    /// Host as written in a URI or `Host` header, IPv6 in brackets.
+    pub fn uri_host(&self) -> String {
+        match self {
+            TargetAddr::Ip(SocketAddr::V6(addr)) => format!("[{}]", addr.ip()),
+            _ => self.host(),
+        }
+    }
+}
+impl Default for TargetAddr {
+    fn default() -> Self {
+        TargetAddr::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 80))
+    }
+}
+impl fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetAddr::Ip(addr) => write!(f, "{}", addr),
+            TargetAddr::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+impl FromStr for TargetAddr {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(TargetAddr::Ip(addr));
+        }
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("missing port in \"{}\"", s))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| format!("invalid port in \"{}\"", s))?;
+        if host.is_empty() || host.len() > 255 || host.contains(':') {
+            return Err(format!("invalid host in \"{}\"", s));
+        }
+        Ok(TargetAddr::Domain(host.to_string(), port))
+    }
+}
+
+/// CD field of a SOCKS4 server response.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Socks4Reply {
+    Granted,
+    RejectedOrFailed,
+    /// The server cannot reach identd on the client.
+    IdentdUnreachable,
+    /// identd reported a different user id than the request.
+    IdentdMismatch,
+    Unknown(u8),
+}
+impl From<u8> for Socks4Reply {
+    fn from(code: u8) -> Self {
+        match code {
+            0x5a => Socks4Reply::Granted,
+            0x5b => Socks4Reply::RejectedOrFailed,
+            0x5c => Socks4Reply::IdentdUnreachable,
+            0x5d => Socks4Reply::IdentdMismatch,
+            other => Socks4Reply::Unknown(other),
+        }
+    }
+}
+impl fmt::Display for Socks4Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks4Reply::Granted => write!(f, "request granted"),
+            Socks4Reply::RejectedOrFailed => write!(f, "request rejected or failed"),
+            Socks4Reply::IdentdUnreachable => write!(f, "identd unreachable"),
+            Socks4Reply::IdentdMismatch => write!(f, "identd user id mismatch"),
+            Socks4Reply::Unknown(code) => write!(f, "unknown reply code {:#04x}", code),
+        }
+    }
+}
+
+/// Reply field of a SOCKS5 server response (RFC 1928 section 6).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Socks5Reply {
+    Succeeded,
+    GeneralFailure,
+    NotAllowed,
+    NetworkUnreachable,
+    HostUnreachable,
+    ConnectionRefused,
+    TtlExpired,
+    CommandNotSupported,
+    AddressTypeNotSupported,
+    Unassigned(u8),
+}
+impl From<u8> for Socks5Reply {
+    fn from(code: u8) -> Self {
+        match code {
+            0x00 => Socks5Reply::Succeeded,
+            0x01 => Socks5Reply::GeneralFailure,
+            0x02 => Socks5Reply::NotAllowed,
+            0x03 => Socks5Reply::NetworkUnreachable,
+            0x04 => Socks5Reply::HostUnreachable,
+            0x05 => Socks5Reply::ConnectionRefused,
+            0x06 => Socks5Reply::TtlExpired,
+            0x07 => Socks5Reply::CommandNotSupported,
+            0x08 => Socks5Reply::AddressTypeNotSupported,
+            other => Socks5Reply::Unassigned(other),
+        }
+    }
+}
+impl fmt::Display for Socks5Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks5Reply::Succeeded => write!(f, "succeeded"),
+            Socks5Reply::GeneralFailure => write!(f, "general SOCKS server failure"),
+            Socks5Reply::NotAllowed => write!(f, "connection not allowed by ruleset"),
+            Socks5Reply::NetworkUnreachable => write!(f, "network unreachable"),
+            Socks5Reply::HostUnreachable => write!(f, "host unreachable"),
+            Socks5Reply::ConnectionRefused => write!(f, "connection refused"),
+            Socks5Reply::TtlExpired => write!(f, "TTL expired"),
+            Socks5Reply::CommandNotSupported => write!(f, "command not supported"),
+            Socks5Reply::AddressTypeNotSupported => write!(f, "address type not supported"),
+            Socks5Reply::Unassigned(code) => write!(f, "unassigned reply code {:#04x}", code),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SocksError {
+    Io(io::Error),
+    /// The server answered with a different protocol version.
+    BadVersion(u8),
+    /// None of the offered authentication methods were accepted.
+    NoAcceptableMethod,
+    /// The server picked a method that was never offered.
+    UnexpectedMethod(u8),
+    /// Username or password do not fit the 255 byte RFC 1929 fields.
+    CredentialsTooLong,
+    /// The server refused the username/password sub-negotiation.
+    AuthRejected(u8),
+    UnknownAddressType(u8),
+    DomainTooLong,
+    /// SOCKS4 has no way to address an IPv6 destination.
+    Ipv6Unsupported,
+    Socks4Reply(Socks4Reply),
+    Socks5Reply(Socks5Reply),
+}
+impl fmt::Display for SocksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocksError::Io(e) => write!(f, "io error: {}", e),
+            SocksError::BadVersion(v) => write!(f, "unexpected protocol version {}", v),
+            SocksError::NoAcceptableMethod => write!(f, "no acceptable authentication method"),
+            SocksError::UnexpectedMethod(m) => {
+                write!(f, "server selected unoffered method {:#04x}", m)
+            }
+            SocksError::CredentialsTooLong => write!(f, "username or password too long"),
+            SocksError::AuthRejected(status) => {
+                write!(f, "authentication rejected (status {:#04x})", status)
+            }
+            SocksError::UnknownAddressType(t) => write!(f, "unknown address type {:#04x}", t),
+            SocksError::DomainTooLong => write!(f, "domain name longer than 255 bytes"),
+            SocksError::Ipv6Unsupported => write!(f, "socks4 cannot connect to ipv6 targets"),
+            SocksError::Socks4Reply(reply) => write!(f, "socks4 reply: {}", reply),
+            SocksError::Socks5Reply(reply) => write!(f, "socks5 reply: {}", reply),
+        }
+    }
+}
+impl std::error::Error for SocksError {}
+impl From<io::Error> for SocksError {
+    fn from(e: io::Error) -> Self {
+        SocksError::Io(e)
+    }
+}
+
+fn encode_addr(target: &TargetAddr, packet: &mut Vec<u8>) -> Result<(), SocksError> {
+    match target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => {
+            packet.push(0x01);
+            packet.extend_from_slice(&addr.ip().octets());
+        }
+        TargetAddr::Ip(SocketAddr::V6(addr)) => {
+            packet.push(0x04);
+            packet.extend_from_slice(&addr.ip().octets());
+        }
+        TargetAddr::Domain(host, _) => {
+            if host.len() > 255 {
+                return Err(SocksError::DomainTooLong);
+            }
+            packet.push(0x03);
+            packet.push(host.len() as u8);
+            packet.extend_from_slice(host.as_bytes());
+        }
+    }
+    packet.extend_from_slice(&target.port().to_be_bytes());
+    Ok(())
+}
+
+async fn read_addr<S>(stream: &mut S, atyp: u8) -> Result<TargetAddr, SocksError>
+where
+    S: AsyncRead + Unpin,
+{
+    match atyp {
+        0x01 => {
+            let mut buf = [0; 6];
+            stream.read_exact(&mut buf).await?;
+            let ip = Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3]);
+            let port = u16::from_be_bytes([buf[4], buf[5]]);
+            Ok(TargetAddr::Ip(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        0x04 => {
+            let mut buf = [0; 18];
+            stream.read_exact(&mut buf).await?;
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&buf[..16]);
+            let port = u16::from_be_bytes([buf[16], buf[17]]);
+            Ok(TargetAddr::Ip(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        0x03 => {
+            let mut len = [0; 1];
+            stream.read_exact(&mut len).await?;
+            let mut buf = vec![0; len[0] as usize + 2];
+            stream.read_exact(&mut buf).await?;
+            let port = u16::from_be_bytes([buf[buf.len() - 2], buf[buf.len() - 1]]);
+            buf.truncate(len[0] as usize);
+            Ok(TargetAddr::Domain(
+                String::from_utf8_lossy(&buf).into_owned(),
+                port,
+            ))
+        }
+        other => Err(SocksError::UnknownAddressType(other)),
+    }
+}
+
+/// Username/password sub-negotiation (RFC 1929).
+async fn socks5_authenticate<S>(stream: &mut S, credentials: &Credentials) -> Result<(), SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let username = credentials.username.as_bytes();
+    let password = credentials.password.as_bytes();
+    if username.len() > 255 || password.len() > 255 {
+        return Err(SocksError::CredentialsTooLong);
+    }
+    let mut request = vec![1, username.len() as u8];
+    request.extend_from_slice(username);
+    request.push(password.len() as u8);
+    request.extend_from_slice(password);
+    stream.write_all(&request).await?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 1 {
+        return Err(SocksError::BadVersion(reply[0]));
+    }
+    match reply[1] {
+        0 => Ok(()),
+        status => Err(SocksError::AuthRejected(status)),
+    }
+}
+
+/// Runs the SOCKS5 method negotiation, authenticating when credentials are
+/// given, and a CONNECT request to `target` over an already connected
+/// stream, returning the bound address reported by the server.
+pub async fn socks5_connect<S>(
+    stream: &mut S,
+    target: &TargetAddr,
+    credentials: Option<&Credentials>,
+) -> Result<TargetAddr, SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    socks5_handshake(stream, target, credentials, &mut Trace::new()).await
+}
+
+/// `socks5_connect`, recording each stage of the handshake in `trace`.
+pub(crate) async fn socks5_handshake<S>(
+    stream: &mut S,
+    target: &TargetAddr,
+    credentials: Option<&Credentials>,
+    trace: &mut Trace,
+) -> Result<TargetAddr, SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    trace.enter(Stage::Greeting);
+    let greeting: &[u8] = match credentials {
+        Some(_) => &[
+            5, // protocol version
+            2, // method count
+            0, // no auth
+            2, // username/password
+        ],
+        None => &[
+            5, // protocol version
+            1, // method count
+            0, // no auth
+        ],
+    };
+    stream.write_all(greeting).await?;
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != 5 {
+        return Err(SocksError::BadVersion(choice[0]));
+    }
+    match (choice[1], credentials) {
+        (0x00, _) => {}
+        (0x02, Some(credentials)) => {
+            trace.enter(Stage::Auth);
+            socks5_authenticate(stream, credentials).await?
+        }
+        (0xff, _) => return Err(SocksError::NoAcceptableMethod),
+        (other, _) => return Err(SocksError::UnexpectedMethod(other)),
+    }
+    trace.enter(Stage::Tunnel);
+    let mut request = vec![
+        5, // protocol version
+        1, // CONNECT
+        0, // reserved
+    ];
+    encode_addr(target, &mut request)?;
+    stream.write_all(&request).await?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        return Err(SocksError::BadVersion(reply[0]));
+    }
+    match Socks5Reply::from(reply[1]) {
+        Socks5Reply::Succeeded => read_addr(stream, reply[3]).await,
+        code => Err(SocksError::Socks5Reply(code)),
+    }
+}
+
+/// Sends a SOCKS4 CONNECT request to `target` over an already connected
+/// stream. Domain targets are sent the SOCKS4a way, letting the proxy
+/// resolve the name. Returns the address reported in the reply.
+pub async fn socks4_connect<S>(
+    stream: &mut S,
+    target: &TargetAddr,
+    user_id: &str,
+) -> Result<TargetAddr, SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = vec![
+        4, // protocol version
+        1, // CONNECT
+    ];
+    request.extend_from_slice(&target.port().to_be_bytes());
+    match target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => {
+            request.extend_from_slice(&addr.ip().octets());
+            request.extend_from_slice(user_id.as_bytes());
+            request.push(0);
+        }
+        TargetAddr::Ip(SocketAddr::V6(_)) => return Err(SocksError::Ipv6Unsupported),
+        TargetAddr::Domain(host, _) => {
+            // 0.0.0.x with a non-zero x tells the server a domain follows
+            request.extend_from_slice(&[0, 0, 0, 1]);
+            request.extend_from_slice(user_id.as_bytes());
+            request.push(0);
+            request.extend_from_slice(host.as_bytes());
+            request.push(0);
+        }
+    }
+    stream.write_all(&request).await?;
+    let mut reply = [0; 8];
+    stream.read_exact(&mut reply).await?;
+    // the reply version should be 0, some servers echo 4 instead
+    if reply[0] != 0 && reply[0] != 4 {
+        return Err(SocksError::BadVersion(reply[0]));
+    }
+    match Socks4Reply::from(reply[1]) {
+        Socks4Reply::Granted => {
+            let ip = Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+            let port = u16::from_be_bytes([reply[2], reply[3]]);
+            Ok(TargetAddr::Ip(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        code => Err(SocksError::Socks4Reply(code)),
+    }
+}
+
+#[tokio::test]
+async fn test_socks5_connect() {
+    use async_std::net::{TcpListener, TcpStream};
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = async_std::task::spawn(async move {
+        for reply in [0x00u8, 0x05] {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut greeting = [0; 3];
+            socket.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            socket.write_all(&[5, 0]).await.unwrap();
+            let mut head = [0; 5];
+            socket.read_exact(&mut head).await.unwrap();
+            assert_eq!(head, [5, 1, 0, 3, 11]);
+            let mut rest = [0; 13];
+            socket.read_exact(&mut rest).await.unwrap();
+            assert_eq!(&rest[..11], b"example.com");
+            socket
+                .write_all(&[5, reply, 0, 1, 10, 0, 0, 1, 0x1f, 0x90])
+                .await
+                .unwrap();
+        }
+    });
+    let target: TargetAddr = "example.com:443".parse().unwrap();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let bound = socks5_connect(&mut stream, &target, None).await.unwrap();
+    assert_eq!(bound, "10.0.0.1:8080".parse().unwrap());
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    match socks5_connect(&mut stream, &target, None).await {
+        Err(SocksError::Socks5Reply(Socks5Reply::ConnectionRefused)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    server.await;
+}
+
+#[tokio::test]
+async fn test_socks4_connect() {
+    use async_std::net::{TcpListener, TcpStream};
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = async_std::task::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 9];
+        socket.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, [4, 1, 0, 80, 1, 1, 1, 1, 0]);
+        socket
+            .write_all(&[0, 0x5a, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 24];
+        socket.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request[..8], &[4, 1, 1, 187, 0, 0, 0, 1]);
+        assert_eq!(&request[8..], b"bob\0example.com\0");
+        socket
+            .write_all(&[0, 0x5b, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+    });
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert!(socks4_connect(&mut stream, &TargetAddr::default(), "")
+        .await
+        .is_ok());
+    let target: TargetAddr = "example.com:443".parse().unwrap();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    match socks4_connect(&mut stream, &target, "bob").await {
+        Err(SocksError::Socks4Reply(Socks4Reply::RejectedOrFailed)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    server.await;
+}
+
+#[tokio::test]
+async fn test_socks5_authenticate() {
+    use async_std::net::{TcpListener, TcpStream};
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = async_std::task::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut greeting = [0; 4];
+        socket.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [5, 2, 0, 2]);
+        socket.write_all(&[5, 2]).await.unwrap();
+        let mut auth = [0; 12];
+        socket.read_exact(&mut auth).await.unwrap();
+        assert_eq!(&auth, b"\x01\x03bob\x06hunter");
+        socket.write_all(&[1, 1]).await.unwrap();
+    });
+    let credentials = Credentials::new("bob", "hunter");
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    match socks5_connect(&mut stream, &TargetAddr::default(), Some(&credentials)).await {
+        Err(SocksError::AuthRejected(1)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    server.await;
+}
@@ -0,0 +1,113 @@
+use crate::{Host, Proxy};
+use async_tls::TlsConnector;
+use rustls::{
+    Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Name handed to rustls when a proxy has no usable server name; SNI is
+/// turned off so it never goes on the wire.
+const PLACEHOLDER_NAME: &str = "proxy.invalid";
+
+/// How the certificate presented by an HTTPS proxy is checked.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub enum CertPolicy {
+    /// Against the webpki roots, for the SNI name.
+    #[default]
+    Verify,
+    /// Any certificate goes, as most HTTPS proxies on bare IPs are
+    /// self-signed.
+    AcceptInvalid,
+}
+impl fmt::Display for CertPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertPolicy::Verify => write!(f, "verify"),
+            CertPolicy::AcceptInvalid => write!(f, "accept-invalid"),
+        }
+    }
+}
+impl FromStr for CertPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "verify" => Ok(CertPolicy::Verify),
+            "accept-invalid" | "insecure" => Ok(CertPolicy::AcceptInvalid),
+            _ => Err(format!("unknown certificate policy \"{}\"", s)),
+        }
+    }
+}
+
+/// Built client configurations by certificate policy and whether SNI is on.
+type ClientConfigs = HashMap<(CertPolicy, bool), Arc<ClientConfig>>;
+
+/// TLS settings for the connection to an HTTPS proxy. Client
+/// configurations are built on first use and shared by clones.
+#[derive(Clone, Default)]
+pub struct ProxyTls {
+    /// Server name sent to every proxy; the proxy's host when unset, or no
+    /// SNI at all for IP hosts.
+    pub sni: Option<String>,
+    pub certificates: CertPolicy,
+    configs: Arc<Mutex<ClientConfigs>>,
+}
+impl fmt::Debug for ProxyTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyTls")
+            .field("sni", &self.sni)
+            .field("certificates", &self.certificates)
+            .finish_non_exhaustive()
+    }
+}
+impl ProxyTls {
+    /// Name the proxy is expected to present, if any.
+    fn server_name<'a>(&'a self, proxy: &'a Proxy) -> Option<&'a str> {
+        match (&self.sni, &proxy.host) {
+            (Some(sni), _) => Some(sni.as_str()),
+            (None, Host::Domain(name)) => Some(name.as_str()),
+            (None, Host::Ip(_)) => None,
+        }
+    }
+    /// Connector for `proxy` and the name to pass to `TlsConnector::connect`.
+    pub(crate) fn connector<'a>(&'a self, proxy: &'a Proxy) -> (TlsConnector, &'a str) {
+        let name = self.server_name(proxy);
+        let config = self
+            .configs
+            .lock()
+            .unwrap()
+            .entry((self.certificates, name.is_some()))
+            .or_insert_with(|| Arc::new(client_config(self.certificates, name.is_some())))
+            .clone();
+        (TlsConnector::from(config), name.unwrap_or(PLACEHOLDER_NAME))
+    }
+}
+
+fn client_config(certificates: CertPolicy, enable_sni: bool) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    match certificates {
+        CertPolicy::Verify => config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+        CertPolicy::AcceptInvalid => config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AcceptAnyCertificate)),
+    }
+    config.enable_sni = enable_sni;
+    config
+}
+
+struct AcceptAnyCertificate;
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
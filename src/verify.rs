@@ -0,0 +1,234 @@
+use crate::{basic_auth, Credentials, HttpError, TargetAddr};
+use async_std::io::{ReadExt, WriteExt};
+use futures::{AsyncRead, AsyncWrite};
+use httparse::{Response, EMPTY_HEADER};
+use std::fmt;
+use std::str::FromStr;
+
+const MAXIMUM_RESPONSE_HEADERS: usize = 64;
+const MAXIMUM_RESPONSE_SIZE: usize = 256 * 1024;
+
+/// `http://` or `https://` URL requested through a proxy tunnel.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TargetUrl {
+    pub tls: bool,
+    pub addr: TargetAddr,
+    pub path: String,
+}
+impl fmt::Display for TargetUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        write!(f, "{}://{}{}", scheme, self.addr, self.path)
+    }
+}
+impl FromStr for TargetUrl {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tls, rest) = match s.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+            _ => return Err(format!("\"{}\" is not an http(s) url", s)),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let default_port = if tls { 443 } else { 80 };
+        // a port is present when the authority ends in `:digits` outside of
+        // an IPv6 literal
+        let has_port = match authority.rsplit_once(':') {
+            Some((host, _)) => !host.is_empty() && (!host.contains(':') || host.ends_with(']')),
+            None => false,
+        };
+        let addr = if has_port {
+            authority.parse::<TargetAddr>()?
+        } else {
+            format!("{}:{}", authority, default_port).parse::<TargetAddr>()?
+        };
+        // rustls only checks certificates against DNS names
+        if tls && matches!(addr, TargetAddr::Ip(_)) {
+            return Err(format!(
+                "\"{}\" needs a host name, certificates of ip addresses cannot be verified",
+                s
+            ));
+        }
+        Ok(TargetUrl {
+            tls,
+            addr,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl TargetUrl {
+    /// Value of the `Host` header, with the port unless it is the scheme's
+    /// default.
+    fn host_header(&self) -> String {
+        let default_port = if self.tls { 443 } else { 80 };
+        match self.addr.port() {
+            port if port == default_port => self.addr.uri_host(),
+            port => format!("{}:{}", self.addr.uri_host(), port),
+        }
+    }
+}
+
+/// What a tunnelled request to `url` must return for a proxy to pass.
+#[derive(Clone, Debug)]
+pub struct VerifyConfig {
+    pub url: TargetUrl,
+    /// Required status code, any 2xx when unset.
+    pub status: Option<u16>,
+    /// Text the response body must contain.
+    pub body: Option<String>,
+}
+impl VerifyConfig {
+    pub fn new(url: TargetUrl) -> Self {
+        VerifyConfig {
+            url,
+            status: None,
+            body: None,
+        }
+    }
+}
+
+/// A response read through a tunnel.
+pub(crate) struct Fetched {
+    pub(crate) status: u16,
+    pub(crate) body: Vec<u8>,
+}
+
+/// Sends `request` and reads the answer until the server closes the
+/// connection. `on_first_byte` runs as soon as the first byte arrives.
+pub(crate) async fn fetch<S, F>(
+    stream: &mut S,
+    request: &str,
+    on_first_byte: F,
+) -> Result<Fetched, HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(),
+{
+    stream.write_all(request.as_bytes()).await?;
+    let mut on_first_byte = Some(on_first_byte);
+    let mut buf = vec![0; MAXIMUM_RESPONSE_SIZE];
+    let mut len = 0;
+    while len < buf.len() {
+        let read = stream.read(&mut buf[len..]).await?;
+        if read == 0 {
+            break;
+        }
+        if let Some(on_first_byte) = on_first_byte.take() {
+            on_first_byte();
+        }
+        len += read;
+    }
+    let mut response_headers = [EMPTY_HEADER; MAXIMUM_RESPONSE_HEADERS];
+    let mut response = Response::new(&mut response_headers[..]);
+    match response.parse(&buf[..len]) {
+        Ok(httparse::Status::Complete(head)) => Ok(Fetched {
+            status: response.code.ok_or(HttpError::Malformed)?,
+            body: buf[head..len].to_vec(),
+        }),
+        _ => Err(HttpError::Malformed),
+    }
+}
+
+pub(crate) fn get_request(url: &TargetUrl) -> String {
+    format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         User-Agent: open_proxies\r\n\
+         Accept: */*\r\n\
+         Connection: close\r\n\r\n",
+        url.path,
+        url.host_header()
+    )
+}
+
+/// Absolute-form GET for `url`, sent straight to an HTTP forward proxy.
+pub(crate) fn forward_request(url: &TargetUrl, credentials: Option<&Credentials>) -> String {
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         User-Agent: open_proxies\r\n\
+         Accept: */*\r\n",
+        url,
+        url.host_header()
+    );
+    if let Some(credentials) = credentials {
+        request.push_str(&format!(
+            "Proxy-Authorization: {}\r\n",
+            basic_auth(credentials)
+        ));
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    request
+}
+
+/// Requests `verify.url` over an open tunnel, wrapping it in TLS for https
+/// urls, and checks the answer against the expected status and body.
+pub(crate) async fn verify<S, F>(
+    stream: &mut S,
+    verify: &VerifyConfig,
+    on_first_byte: F,
+) -> Result<Fetched, HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(),
+{
+    let request = get_request(&verify.url);
+    let fetched = if verify.url.tls {
+        let connector = async_tls::TlsConnector::default();
+        let mut tls = connector
+            .connect(verify.url.addr.host().as_str(), &mut *stream)
+            .await?;
+        fetch(&mut tls, &request, on_first_byte).await?
+    } else {
+        fetch(stream, &request, on_first_byte).await?
+    };
+    check_answer(&fetched, verify)?;
+    Ok(fetched)
+}
+
+/// Checks a fetched answer against the expected status and body.
+pub(crate) fn check_answer(fetched: &Fetched, verify: &VerifyConfig) -> Result<(), HttpError> {
+    let status_ok = match verify.status {
+        Some(status) => fetched.status == status,
+        None => (200..300).contains(&fetched.status),
+    };
+    if !status_ok {
+        return Err(HttpError::Status(fetched.status));
+    }
+    if let Some(body) = &verify.body {
+        if !String::from_utf8_lossy(&fetched.body).contains(body.as_str()) {
+            return Err(HttpError::BodyMismatch);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_target_url_parse() {
+    let url: TargetUrl = "https://example.com".parse().unwrap();
+    assert_eq!(url.addr, "example.com:443".parse().unwrap());
+    assert_eq!(url.path, "/");
+    let url: TargetUrl = "http://[::1]:8080/judge?x=1".parse().unwrap();
+    assert!(!url.tls);
+    assert_eq!(url.addr, "[::1]:8080".parse().unwrap());
+    assert_eq!(url.path, "/judge?x=1");
+    let url: TargetUrl = "http://[::1]/".parse().unwrap();
+    assert_eq!(url.addr, "[::1]:80".parse().unwrap());
+    assert!("ftp://example.com".parse::<TargetUrl>().is_err());
+    assert!("https://1.2.3.4/".parse::<TargetUrl>().is_err());
+    assert!("http://1.2.3.4/".parse::<TargetUrl>().is_ok());
+}
+
+#[test]
+fn test_host_header_port() {
+    let url: TargetUrl = "http://judge.example:8080/echo".parse().unwrap();
+    assert!(get_request(&url).contains("\r\nHost: judge.example:8080\r\n"));
+    let url: TargetUrl = "https://judge.example/echo".parse().unwrap();
+    assert!(forward_request(&url, None).contains("\r\nHost: judge.example\r\n"));
+    let url: TargetUrl = "http://[::1]:8080/".parse().unwrap();
+    assert!(get_request(&url).contains("\r\nHost: [::1]:8080\r\n"));
+}